@@ -12,6 +12,7 @@ use sqlparser::ast::Ident;
 enum Prefix {
     Event,
     Function,
+    Trace,
 }
 
 #[derive(Debug)]
@@ -34,6 +35,7 @@ impl Schema {
             [name] => (Prefix::Event, Ident::new(*name)),
             [pref, name] => match *pref {
                 "function" => (Prefix::Function, Ident::new(*name)),
+                "trace" => (Prefix::Trace, Ident::new(*name)),
                 _ => (Prefix::Event, Ident::new(*name)),
             },
             _ => return Err(eyre!("invalid prefix")),
@@ -58,7 +60,7 @@ impl Schema {
     pub fn sql(&self) -> HashMap<Ident, String> {
         let source_column = match self.prefix {
             Prefix::Event => "data",
-            Prefix::Function => "substring(input, 5)",
+            Prefix::Function | Prefix::Trace => "substring(input, 5)",
         };
         self.fields
             .topics_sql()
@@ -78,7 +80,7 @@ impl Schema {
     pub fn sighash_sql_predicate(&self) -> String {
         match self.prefix {
             Prefix::Event => format!(r#"topics[1] = '\x{}'"#, hex::encode(self.sighash())),
-            Prefix::Function => format!(
+            Prefix::Function | Prefix::Trace => format!(
                 r#"(substring(input, 1, 4) = '\x{}' and input is not null and octet_length(input) >= {})"#,
                 hex::encode(&self.sighash()[0..4]),
                 self.fields.size()
@@ -90,6 +92,7 @@ impl Schema {
         match self.prefix {
             Prefix::Event => String::from("logs"),
             Prefix::Function => String::from("txs"),
+            Prefix::Trace => String::from("traces"),
         }
     }
 }
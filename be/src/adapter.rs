@@ -0,0 +1,335 @@
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+use alloy::primitives::{Bytes, U256};
+use eyre::{Context, Result};
+use serde_json::Value;
+use shared::jrpc;
+use sqlparser::ast;
+use tokio_postgres::types::{Json, ToSql, Type};
+
+/// Chain families add fields to the rpc's transactions and receipts.
+/// An adapter names the json fields a family adds and the txs columns
+/// they're copied to so copy_txs doesn't need to know about each family.
+/// Columns must also be added to txs in schema.sql.
+pub trait Adapter: Send + Sync {
+    /// Stored in config.adapter
+    fn name(&self) -> &'static str;
+
+    fn columns(&self) -> &'static [Column] {
+        &[]
+    }
+
+    /// Values for columns() in the same order.
+    fn values(&self, tx: &jrpc::Tx) -> Result<Vec<Box<dyn ToSql + Sync + Send>>> {
+        self.columns()
+            .iter()
+            .map(|c| {
+                let extra = match c.source {
+                    Source::Tx => &tx.extra,
+                    Source::Receipt => match tx.receipt.as_ref() {
+                        Some(receipt) => &receipt.extra,
+                        None => return Ok(c.kind.null()),
+                    },
+                };
+                match extra.get(c.field) {
+                    None | Some(Value::Null) => Ok(c.kind.null()),
+                    Some(v) => c
+                        .kind
+                        .value(v)
+                        .wrap_err_with(|| format!("decoding {} for tx {}", c.field, tx.hash)),
+                }
+            })
+            .collect()
+    }
+
+    /// The json fields the rpc client needs to collect for values()
+    fn extra_fields(&self) -> jrpc::ExtraFields {
+        let fields = |source: Source| {
+            self.columns()
+                .iter()
+                .filter(|c| c.source == source)
+                .map(|c| c.field)
+                .collect()
+        };
+        jrpc::ExtraFields {
+            tx: fields(Source::Tx),
+            receipt: fields(Source::Receipt),
+        }
+    }
+
+    /// Used by the query planner the same way as the base txs columns
+    fn column_type(&self, name: &str) -> Option<ast::DataType> {
+        self.columns()
+            .iter()
+            .find(|c| c.name == name)
+            .map(|c| c.kind.data_type())
+    }
+}
+
+impl fmt::Debug for dyn Adapter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl PartialEq for dyn Adapter {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name()
+    }
+}
+
+impl Eq for dyn Adapter {}
+
+impl Hash for dyn Adapter {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name().hash(state)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    Tx,
+    Receipt,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Kind {
+    Bytes,
+    Numeric,
+    Json,
+}
+
+impl Kind {
+    pub fn pg_type(&self) -> Type {
+        match self {
+            Kind::Bytes => Type::BYTEA,
+            Kind::Numeric => Type::NUMERIC,
+            Kind::Json => Type::JSONB,
+        }
+    }
+
+    pub fn data_type(&self) -> ast::DataType {
+        match self {
+            Kind::Bytes => ast::DataType::Bytea,
+            Kind::Numeric => ast::DataType::Numeric(ast::ExactNumberInfo::None),
+            Kind::Json => ast::DataType::JSONB,
+        }
+    }
+
+    fn null(&self) -> Box<dyn ToSql + Sync + Send> {
+        match self {
+            Kind::Bytes => Box::new(None::<Vec<u8>>),
+            Kind::Numeric => Box::new(None::<U256>),
+            Kind::Json => Box::new(None::<Json<Value>>),
+        }
+    }
+
+    fn value(&self, v: &Value) -> Result<Box<dyn ToSql + Sync + Send>> {
+        Ok(match self {
+            Kind::Bytes => Box::new(serde_json::from_value::<Bytes>(v.clone())?.to_vec()),
+            Kind::Numeric => Box::new(serde_json::from_value::<U256>(v.clone())?),
+            Kind::Json => Box::new(Json(v.clone())),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Column {
+    /// txs column
+    pub name: &'static str,
+    /// json field in the tx or receipt
+    pub field: &'static str,
+    pub source: Source,
+    pub kind: Kind,
+}
+
+pub struct Ethereum;
+
+impl Adapter for Ethereum {
+    fn name(&self) -> &'static str {
+        "ethereum"
+    }
+}
+
+/// Deposit txs and the L1 data fee
+pub struct OpStack;
+
+impl Adapter for OpStack {
+    fn name(&self) -> &'static str {
+        "op"
+    }
+
+    fn columns(&self) -> &'static [Column] {
+        &[
+            Column {
+                name: "source_hash",
+                field: "sourceHash",
+                source: Source::Tx,
+                kind: Kind::Bytes,
+            },
+            Column {
+                name: "mint",
+                field: "mint",
+                source: Source::Tx,
+                kind: Kind::Numeric,
+            },
+            Column {
+                name: "l1_fee",
+                field: "l1Fee",
+                source: Source::Receipt,
+                kind: Kind::Numeric,
+            },
+            Column {
+                name: "l1_gas_used",
+                field: "l1GasUsed",
+                source: Source::Receipt,
+                kind: Kind::Numeric,
+            },
+            Column {
+                name: "l1_gas_price",
+                field: "l1GasPrice",
+                source: Source::Receipt,
+                kind: Kind::Numeric,
+            },
+        ]
+    }
+}
+
+pub struct Arbitrum;
+
+impl Adapter for Arbitrum {
+    fn name(&self) -> &'static str {
+        "arbitrum"
+    }
+
+    fn columns(&self) -> &'static [Column] {
+        &[Column {
+            name: "gas_used_for_l1",
+            field: "gasUsedForL1",
+            source: Source::Receipt,
+            kind: Kind::Numeric,
+        }]
+    }
+}
+
+/// Fees paid in an ERC-20 instead of the native token
+pub struct Celo;
+
+impl Adapter for Celo {
+    fn name(&self) -> &'static str {
+        "celo"
+    }
+
+    fn columns(&self) -> &'static [Column] {
+        &[Column {
+            name: "fee_currency",
+            field: "feeCurrency",
+            source: Source::Tx,
+            kind: Kind::Bytes,
+        }]
+    }
+}
+
+pub static ALL: &[&dyn Adapter] = &[&Ethereum, &OpStack, &Arbitrum, &Celo];
+
+pub fn get(name: &str) -> Option<&'static dyn Adapter> {
+    ALL.iter().find(|a| a.name() == name).copied()
+}
+
+/// The type of an adapter column regardless of which chain it belongs to
+pub fn column_type(name: &str) -> Option<ast::DataType> {
+    ALL.iter().find_map(|a| a.column_type(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{B256, U256, U64};
+    use serde_json::json;
+    use shared::jrpc;
+
+    use super::{Adapter, OpStack};
+    use crate::{api, partition, sync};
+
+    static SCHEMA: &str = include_str!("./sql/schema.sql");
+
+    fn deposit() -> jrpc::Block {
+        let extra = OpStack.extra_fields();
+        let json = json!({
+            "hash": B256::with_last_byte(1),
+            "parentHash": B256::ZERO,
+            "number": "0x1",
+            "nonce": "0x0",
+            "timestamp": "0x1",
+            "size": "0x0",
+            "gasLimit": "0x0",
+            "gasUsed": "0x0",
+            "logsBloom": format!("0x{}", "00".repeat(256)),
+            "receiptsRoot": B256::ZERO,
+            "stateRoot": B256::ZERO,
+            "extraData": "0x",
+            "miner": "0x4200000000000000000000000000000000000011",
+            "transactions": [{
+                "hash": B256::with_last_byte(2),
+                "transactionIndex": "0x0",
+                "from": "0x4200000000000000000000000000000000000015",
+                "gas": "0xf4240",
+                "sourceHash": "0x0102",
+            }],
+        });
+        let mut block = extra.block(&json.to_string()).unwrap();
+        let receipt = json!({
+            "transactionHash": B256::with_last_byte(2),
+            "blockNumber": "0x1",
+            "gasUsed": "0x5208",
+            "cumulativeGasUsed": "0x5208",
+            "logsBloom": format!("0x{}", "00".repeat(256)),
+            "l1Fee": "0x2a",
+        });
+        let tx = &mut block.transactions[0];
+        tx.block_timestamp = Some(U64::from(1));
+        tx.receipt = Some(extra.receipt(&receipt.to_string()).unwrap());
+        block
+    }
+
+    #[test]
+    fn test_values() {
+        let mut tx = deposit().transactions.remove(0);
+        let values = OpStack.values(&tx).unwrap();
+        assert_eq!(values.len(), OpStack.columns().len());
+        assert_eq!(format!("{:?}", values[0]), "[1, 2]");
+        assert_eq!(format!("{:?}", values[1]), "None");
+        assert_eq!(format!("{:?}", values[2]), "42");
+
+        tx.extra.insert("sourceHash".to_string(), json!("nope"));
+        assert!(OpStack.values(&tx).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_copy_txs() {
+        let pool = shared::pg::test::new(SCHEMA).await;
+        let mut pg = pool.get().await.unwrap();
+        let pgtx = pg.transaction().await.unwrap();
+        sync::setup_tables(&pgtx, 1, partition::DEFAULT_BLOCKS, 1, 1)
+            .await
+            .unwrap();
+        sync::copy_txs(
+            &pgtx,
+            api::Chain(1),
+            &OpStack,
+            &[deposit()],
+            sync::CopyMode::Merge,
+        )
+        .await
+        .unwrap();
+        let row = pgtx
+            .query_one("select source_hash, l1_fee, fee_currency from txs", &[])
+            .await
+            .unwrap();
+        assert_eq!(row.get::<usize, Vec<u8>>(0), vec![1, 2]);
+        assert_eq!(row.get::<usize, U256>(1), U256::from(42));
+        assert_eq!(row.get::<usize, Option<Vec<u8>>>(2), None);
+    }
+}
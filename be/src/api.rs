@@ -3,6 +3,7 @@ use std::{
     convert::Infallible,
     fmt::{self, Debug},
     net::SocketAddr,
+    path::PathBuf,
     sync::{Arc, Mutex},
 };
 
@@ -21,12 +22,13 @@ use futures::Stream;
 use serde::{Deserialize, Serialize};
 
 use deadpool_postgres::Pool;
+use metrics_exporter_prometheus::PrometheusHandle;
 use serde::ser::SerializeStruct;
 use serde_json::{json, Value};
 use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use url::Url;
 
-use crate::{broadcast, gafe};
+use crate::{broadcast, gafe, metrics};
 
 macro_rules! user_error {
     ($e:expr) => {
@@ -73,7 +75,7 @@ pub async fn handle_status(
             yield Ok(SSEvent::default()
                 .json_data(update)
                 .expect("unable to serialize json"));
-            let active_connections = MAX_ACTIVE_CONNECTIONS - config.active_connections.available_permits();
+            let active_connections = config.active_connections();
             yield Ok(SSEvent::default()
                 .json_data(serde_json::json!({ "active_connections": active_connections }))
                 .expect("unable to serialize json"));
@@ -94,6 +96,12 @@ pub struct Config {
     pub free_limit: Arc<gafe::AccountLimit>,
     pub account_limits: Arc<Mutex<HashMap<String, Arc<gafe::AccountLimit>>>>,
     pub gafe: gafe::Connection,
+    // where retention archives detached partitions
+    pub archive_dir: Option<PathBuf>,
+    pub metrics: Option<PrometheusHandle>,
+    // listened to for config and account limit changes
+    // when set. Otherwise they're only polled.
+    pub fe_url: Option<String>,
 }
 
 const MAX_ACTIVE_CONNECTIONS: usize = 10000;
@@ -111,9 +119,16 @@ impl Config {
             be_pool,
             fe_pool,
             ro_pool,
+            archive_dir: None,
+            metrics: None,
+            fe_url: None,
         }
     }
 
+    pub fn active_connections(&self) -> usize {
+        MAX_ACTIVE_CONNECTIONS - self.active_connections.available_permits()
+    }
+
     pub async fn new_connection(&self) -> Result<OwnedSemaphorePermit, Error> {
         self.active_connections
             .clone()
@@ -281,6 +296,7 @@ pub async fn limit(
         .check_key(&origin_ip.to_string())
         .is_err()
     {
+        metrics::rate_limited(&account_limit);
         return Err(Error::TooManyRequests(Some(String::from(
             "Rate limited. Create or upgrade API Key at: https://www.indexsupply.net",
         ))));
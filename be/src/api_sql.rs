@@ -34,6 +34,8 @@ pub struct Request {
     pub event_signatures: Vec<String>,
     pub query: String,
     pub block_height: Option<u64>,
+    #[serde(default)]
+    pub finalized_only: bool,
 }
 
 impl From<&Request> for user_query::Row {
@@ -58,6 +60,12 @@ type Rows = Vec<Row>;
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Response {
     pub block_height: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub safe_block_height: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finalized_block_height: Option<u64>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
     pub result: Vec<Rows>,
 }
 
@@ -135,16 +143,6 @@ async fn query(
     timeout: Duration,
     requests: &[Request],
 ) -> Result<Response, api::Error> {
-    let queries = requests
-        .iter()
-        .map(|r| {
-            query::sql(
-                &mut cursor::Cursor::new(r.chain.unwrap_or_default(), r.block_height),
-                r.event_signatures.iter().map(|s| s.as_str()).collect(),
-                &r.query,
-            )
-        })
-        .collect::<Result<Vec<_>, _>>()?;
     let mut pg = be_pool.get().await?;
     let pgtx = pg
         .build_transaction()
@@ -157,6 +155,25 @@ async fn query(
         &[],
     )
     .await?;
+    let finality = cursor::Finality::load(&pgtx).await?;
+    let cursors = requests
+        .iter()
+        .map(|r| {
+            let mut cursor = cursor::Cursor::new(r.chain.unwrap_or_default(), r.block_height);
+            if r.finalized_only {
+                cursor::Finality::limit(&mut cursor, &finality);
+            }
+            let q = query::sql(
+                &mut cursor,
+                r.event_signatures.iter().map(|s| s.as_str()).collect(),
+                &r.query,
+            )?;
+            if r.finalized_only {
+                cursor::Finality::check(&cursor)?;
+            }
+            Ok((cursor, q))
+        })
+        .collect::<Result<Vec<_>, api::Error>>()?;
     let chain = requests
         .first()
         .expect("no queries in request")
@@ -170,12 +187,21 @@ async fn query(
         .await?
         .get::<usize, U64>(0)
         .to::<u64>();
+    let block_height = cursors
+        .first()
+        .and_then(|(cursor, _)| cursor.block_limit(chain))
+        .map_or(block_height, |n| block_height.min(n));
     let mut result: Vec<Rows> = Vec::new();
-    for q in queries {
+    for (_, q) in cursors {
         result.push(handle_rows(pgtx.query(&q, &[]).await?)?);
     }
+    let f = finality.get(&chain).copied().unwrap_or_default();
+    let partial = cursor::partial_chains(&pgtx).await?.contains(&chain);
     Ok(Response {
         block_height,
+        safe_block_height: f.safe,
+        finalized_block_height: f.finalized,
+        partial,
         result,
     })
 }
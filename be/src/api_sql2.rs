@@ -48,6 +48,8 @@ pub struct Request {
     #[serde(default)]
     pub signatures: Vec<String>,
     pub query: String,
+    #[serde(default)]
+    pub finalized_only: bool,
 }
 
 #[derive(Serialize)]
@@ -59,6 +61,12 @@ pub struct Column {
 #[derive(Serialize)]
 pub struct Response {
     pub cursor: cursor::Cursor,
+    #[serde(skip_serializing_if = "cursor::Cursor::is_empty")]
+    pub safe: cursor::Cursor,
+    #[serde(skip_serializing_if = "cursor::Cursor::is_empty")]
+    pub finalized: cursor::Cursor,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub partial: Vec<u64>,
     pub columns: Vec<Column>,
     pub rows: Vec<Vec<Value>>,
 }
@@ -161,18 +169,36 @@ async fn query(
         &[],
     )
     .await?;
+    let finality = cursor::Finality::load(&pgtx).await?;
+    let partial = cursor::partial_chains(&pgtx).await?;
     let mut result: Vec<Response> = Vec::new();
     for r in requests {
         let mut cursor = r.cursor.clone();
+        if r.finalized_only {
+            cursor::Finality::limit(&mut cursor, &finality);
+        }
         let q = query::sql(
             &mut cursor,
             r.signatures.iter().map(|s| s.as_str()).collect(),
             &r.query,
         )?;
+        if r.finalized_only {
+            cursor::Finality::check(&cursor)?;
+        }
         let rows = pgtx.query(&q, &[]).await?;
         update_cursor(&pgtx, &mut cursor).await?;
+        let (safe, finalized) = cursor::Finality::cursors(&cursor.chains(), &finality);
+        let partial = cursor
+            .chains()
+            .into_iter()
+            .filter(|c| partial.contains(c))
+            .sorted()
+            .collect();
         result.push(Response {
             cursor,
+            safe,
+            finalized,
+            partial,
             columns: get_columns(&rows),
             rows: get_rows(&rows),
         });
@@ -192,6 +218,7 @@ async fn update_cursor(
             )
             .await?;
         let latest: u64 = row.get::<usize, U64>(0).to();
+        let latest = cursor.block_limit(c).map_or(latest, |n| latest.min(n));
         cursor.set_block_height(c, latest + 1);
     }
     Ok(())
@@ -0,0 +1,417 @@
+use alloy::primitives::U64;
+use be::{leader, partition, sync};
+use clap::{Args as ClapArgs, Parser, Subcommand};
+use itertools::Itertools;
+use shared::pg;
+
+#[derive(Parser)]
+struct Args {
+    #[arg(env = "PG_URL", default_value = "postgres://localhost/be")]
+    pg_url: String,
+    #[arg(env = "PG_URL_FE", default_value = "postgres://localhost/fe")]
+    pg_url_fe: String,
+    #[arg(long = "chain")]
+    chain: u64,
+    /// Print what would be repaired without changing anything
+    #[clap(short = 'n', long = "dry-run", global = true, action = clap::ArgAction::SetTrue)]
+    dry_run: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Re-sync block ranges that are missing from blocks
+    Gaps(Range),
+    /// Re-sync blocks with more than one row at the same natural key
+    Duplicates(Range),
+    /// Re-sync blocks that used gas but have no txs
+    MissingTxs(Range),
+    /// Re-sync blocks whose logs_bloom isn't empty but have no logs
+    MissingLogs(Range),
+    /// Build the unique indexes from schema.sql on the chain's partitions
+    /// without blocking sync. Run duplicates first.
+    UniqueIndexes,
+    /// Delete and re-sync from..=to in one transaction. The blocks on
+    /// both sides of the range must not have been reorged.
+    Resync {
+        #[arg(long = "from")]
+        from: u64,
+        #[arg(long = "to")]
+        to: u64,
+    },
+}
+
+/// Defaults to the chain's earliest and latest local blocks
+#[derive(ClapArgs)]
+struct Range {
+    #[arg(long = "from")]
+    from: Option<u64>,
+    #[arg(long = "to")]
+    to: Option<u64>,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let fe_pool = pg::new_pool(&args.pg_url_fe, 1).expect("unable to create fe pg pool");
+    let be_pool = pg::new_pool(&args.pg_url, 2).expect("unable to create pg pool");
+    let mut pg = be_pool.get().await.expect("unable to get pg from pool");
+    let config = sync::RemoteConfig::load(&fe_pool)
+        .await
+        .expect("loading config")
+        .iter()
+        .find(|c| c.chain == args.chain)
+        .cloned()
+        .expect("unable to find chain");
+
+    let ranges = match args.command {
+        Command::Gaps(range) => {
+            let (from, to) = bounds(&pg, args.chain, &range).await;
+            gaps(&pg, args.chain, from, to).await
+        }
+        Command::Duplicates(range) => {
+            let (from, to) = bounds(&pg, args.chain, &range).await;
+            duplicates(&pg, args.chain, from, to).await
+        }
+        Command::MissingTxs(range) => {
+            if !config.filter.txs {
+                println!("chain {} doesn't store txs", args.chain);
+                return;
+            }
+            let (from, to) = bounds(&pg, args.chain, &range).await;
+            missing_txs(&pg, args.chain, from, to).await
+        }
+        Command::MissingLogs(range) => {
+            if !config.filter.logs || !config.filter.log_filter.is_empty() {
+                println!("chain {} doesn't store every log", args.chain);
+                return;
+            }
+            let (from, to) = bounds(&pg, args.chain, &range).await;
+            missing_logs(&pg, args.chain, from, to).await
+        }
+        Command::UniqueIndexes => {
+            unique_indexes(&mut pg, args.chain, args.dry_run).await;
+            return;
+        }
+        Command::Resync { from, to } => {
+            println!("resync {from}..={to}");
+            if args.dry_run {
+                print_counts(&pg, args.chain, from, to).await;
+            }
+            vec![(from, to)]
+        }
+    };
+    if ranges.is_empty() {
+        println!("nothing to repair");
+    }
+    if args.dry_run || ranges.is_empty() {
+        return;
+    }
+    // stops the chain's sync from writing the same blocks
+    let Some(lock) = leader::Lock::try_acquire(&be_pool, args.chain)
+        .await
+        .expect("locking chain")
+    else {
+        println!(
+            "chain {} is locked by another process. stop it first",
+            args.chain
+        );
+        return;
+    };
+    for (from, to) in ranges {
+        let (blocks, txs, logs) = sync::sync_range(&mut pg, &lock, &config, from, to)
+            .await
+            .expect("sync failed");
+        println!("synced {from}..={to} blocks: {blocks} txs: {txs} logs: {logs}");
+    }
+}
+
+async fn bounds(pg: &tokio_postgres::Client, chain: u64, range: &Range) -> (u64, u64) {
+    let row = pg
+        .query_one(
+            "select min(num), max(num) from blocks where chain = $1",
+            &[&U64::from(chain)],
+        )
+        .await
+        .expect("querying block range");
+    (
+        range
+            .from
+            .unwrap_or(row.get::<usize, Option<i64>>(0).unwrap_or(0) as u64),
+        range
+            .to
+            .unwrap_or(row.get::<usize, Option<i64>>(1).unwrap_or(0) as u64),
+    )
+}
+
+async fn gaps(pg: &tokio_postgres::Client, chain: u64, from: u64, to: u64) -> Vec<(u64, u64)> {
+    pg.query(
+        "
+        select num + 1 as from, next - 1 as to
+        from (
+            select num, lead(num) over (order by num) as next
+            from blocks
+            where chain = $1 and num >= $2 and num <= $3
+        ) t
+        where next > num + 1
+        ",
+        &[&U64::from(chain), &U64::from(from), &U64::from(to)],
+    )
+    .await
+    .expect("finding gaps")
+    .iter()
+    .map(|row| {
+        let (from, to) = (
+            row.get::<&str, U64>("from").to(),
+            row.get::<&str, U64>("to").to(),
+        );
+        println!("gap {from}..={to}");
+        (from, to)
+    })
+    .collect()
+}
+
+async fn duplicates(
+    pg: &tokio_postgres::Client,
+    chain: u64,
+    from: u64,
+    to: u64,
+) -> Vec<(u64, u64)> {
+    pg.query(
+        "
+        select num as block_num, 'blocks' as tbl, count(*) - 1 as extra
+        from blocks
+        where chain = $1 and num >= $2 and num <= $3
+        group by num
+        having count(*) > 1
+        union all
+        select block_num, 'logs' as tbl, count(*) - count(distinct log_idx) as extra
+        from logs
+        where chain = $1 and block_num >= $2 and block_num <= $3
+        group by block_num
+        having count(*) > count(distinct log_idx)
+        union all
+        select block_num, 'txs' as tbl, count(*) - count(distinct idx) as extra
+        from txs
+        where chain = $1 and block_num >= $2 and block_num <= $3
+        group by block_num
+        having count(*) > count(distinct idx)
+        union all
+        select block_num, 'traces' as tbl, count(*) - count(distinct (tx_idx, trace_idx)) as extra
+        from traces
+        where chain = $1 and block_num >= $2 and block_num <= $3
+        group by block_num
+        having count(*) > count(distinct (tx_idx, trace_idx))
+        union all
+        select block_num, 'withdrawals' as tbl, count(*) - count(distinct idx) as extra
+        from withdrawals
+        where chain = $1 and block_num >= $2 and block_num <= $3
+        group by block_num
+        having count(*) > count(distinct idx)
+        order by block_num
+        ",
+        &[&U64::from(chain), &U64::from(from), &U64::from(to)],
+    )
+    .await
+    .expect("finding duplicates")
+    .iter()
+    .map(|row| {
+        let num: u64 = row.get::<&str, U64>("block_num").to();
+        println!(
+            "duplicate {} {} extra: {}",
+            num,
+            row.get::<&str, &str>("tbl"),
+            row.get::<&str, i64>("extra"),
+        );
+        (num, num)
+    })
+    .dedup()
+    .collect()
+}
+
+async fn missing_txs(
+    pg: &tokio_postgres::Client,
+    chain: u64,
+    from: u64,
+    to: u64,
+) -> Vec<(u64, u64)> {
+    pg.query(
+        "
+        select b.num
+        from blocks b
+        where b.chain = $1 and b.num >= $2 and b.num <= $3
+        and b.gas_used > 0
+        and not exists (
+            select 1 from txs t
+            where t.chain = b.chain and t.block_num = b.num
+        )
+        order by b.num
+        ",
+        &[&U64::from(chain), &U64::from(from), &U64::from(to)],
+    )
+    .await
+    .expect("finding blocks with missing txs")
+    .iter()
+    .map(|row| {
+        let num: u64 = row.get::<&str, U64>("num").to();
+        println!("missing txs {num}");
+        (num, num)
+    })
+    .collect()
+}
+
+async fn missing_logs(
+    pg: &tokio_postgres::Client,
+    chain: u64,
+    from: u64,
+    to: u64,
+) -> Vec<(u64, u64)> {
+    pg.query(
+        "
+        select b.num
+        from blocks b
+        where b.chain = $1 and b.num >= $2 and b.num <= $3
+        and b.logs_bloom <> decode(repeat('00', 256), 'hex')
+        and not exists (
+            select 1 from logs l
+            where l.chain = b.chain and l.block_num = b.num
+        )
+        order by b.num
+        ",
+        &[&U64::from(chain), &U64::from(from), &U64::from(to)],
+    )
+    .await
+    .expect("finding blocks with missing logs")
+    .iter()
+    .map(|row| {
+        let num: u64 = row.get::<&str, U64>("num").to();
+        println!("missing logs {num}");
+        (num, num)
+    })
+    .collect()
+}
+
+/// Each unique index is created on the chain's range partitions with
+/// `create index concurrently` and attached to an index created
+/// `on only` the chain's table, which is then attached to the index on
+/// the parent table. The parent's index becomes valid once every
+/// chain's index is attached.
+async fn unique_indexes(pg: &mut tokio_postgres::Client, chain: u64, dry_run: bool) {
+    for (table, name, columns) in partition::UNIQUE_INDEXES {
+        let key = name.strip_prefix(table).unwrap();
+        let chain_table = format!("{table}_c{chain}");
+        let pgtx = pg.transaction().await.expect("starting pg tx");
+        let partitions = partition::list(&pgtx, &chain_table)
+            .await
+            .expect("listing partitions");
+        pgtx.commit().await.expect("committing pg tx");
+        if partitions.is_empty() {
+            continue;
+        }
+        if dry_run {
+            for p in partitions {
+                if attached(pg, &format!("{chain_table}{key}"), &p.name)
+                    .await
+                    .is_none()
+                {
+                    println!("would create {}{key}", p.name);
+                }
+            }
+            continue;
+        }
+        pg.batch_execute(&format!(
+            "create unique index if not exists {name} on only {table} ({columns})"
+        ))
+        .await
+        .expect("creating parent index");
+        let (chain_index, chain_attached) = match attached(pg, name, &chain_table).await {
+            Some(index) => (index, true),
+            None => {
+                let index = format!("{chain_table}{key}");
+                pg.batch_execute(&format!(
+                    "create unique index if not exists {index} on only {chain_table} ({columns})"
+                ))
+                .await
+                .expect("creating chain index");
+                (index, false)
+            }
+        };
+        for p in partitions {
+            if attached(pg, &chain_index, &p.name).await.is_some() {
+                continue;
+            }
+            let index = format!("{}{key}", p.name);
+            let invalid = pg
+                .query_opt(
+                    "select 1 from pg_index where indexrelid = to_regclass($1) and not indisvalid",
+                    &[&index],
+                )
+                .await
+                .expect("checking index")
+                .is_some();
+            if invalid {
+                // left behind by a failed concurrent build
+                pg.batch_execute(&format!("drop index concurrently {index}"))
+                    .await
+                    .expect("dropping invalid index");
+            }
+            println!("create {index}");
+            pg.batch_execute(&format!(
+                "create unique index concurrently if not exists {index} on {} ({columns})",
+                p.name
+            ))
+            .await
+            .expect("creating index. run duplicates first");
+            pg.batch_execute(&format!(
+                "alter index {chain_index} attach partition {index}"
+            ))
+            .await
+            .expect("attaching index");
+        }
+        if !chain_attached {
+            pg.batch_execute(&format!(
+                "alter index {name} attach partition {chain_index}"
+            ))
+            .await
+            .expect("attaching chain index");
+        }
+    }
+}
+
+/// The name of table's index that is attached to the partitioned index
+async fn attached(pg: &tokio_postgres::Client, index: &str, table: &str) -> Option<String> {
+    pg.query_opt(
+        "
+        select c.relname::text as name
+        from pg_inherits i
+        join pg_class c on c.oid = i.inhrelid
+        join pg_index x on x.indexrelid = c.oid
+        where i.inhparent = to_regclass($1) and x.indrelid = to_regclass($2)
+        ",
+        &[&index, &table],
+    )
+    .await
+    .expect("finding attached index")
+    .map(|row| row.get("name"))
+}
+
+async fn print_counts(pg: &tokio_postgres::Client, chain: u64, from: u64, to: u64) {
+    for table in partition::TABLES {
+        let num = match table {
+            "blocks" => "num",
+            _ => "block_num",
+        };
+        let count: i64 = pg
+            .query_one(
+                &format!(
+                    "select count(*) from {table} where chain = $1 and {num} >= $2 and {num} <= $3"
+                ),
+                &[&U64::from(chain), &U64::from(from), &U64::from(to)],
+            )
+            .await
+            .expect("counting rows")
+            .get(0);
+        println!("would delete {count} {table}");
+    }
+}
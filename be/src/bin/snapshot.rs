@@ -0,0 +1,93 @@
+use std::path::PathBuf;
+
+use alloy::primitives::U64;
+use be::{snapshot, sync};
+use clap::{Parser, Subcommand};
+use shared::pg;
+
+static SCHEMA_BE: &str = include_str!("../sql/schema.sql");
+
+#[derive(Parser)]
+struct Args {
+    #[arg(env = "PG_URL", default_value = "postgres://localhost/be")]
+    pg_url: String,
+    #[arg(env = "PG_URL_FE", default_value = "postgres://localhost/fe")]
+    pg_url_fe: String,
+    #[arg(long = "chain")]
+    chain: u64,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Write blocks, txs and logs for a block range to parquet files in dir
+    Export {
+        /// Defaults to the earliest local block
+        #[arg(long = "from")]
+        from: Option<u64>,
+        /// Defaults to the latest local block
+        #[arg(long = "to")]
+        to: Option<u64>,
+        dir: PathBuf,
+    },
+    /// Copy a snapshot from dir into the database.
+    /// be continues syncing from the snapshot's last block.
+    Import { dir: PathBuf },
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt().init();
+    let args = Args::parse();
+    let be_pool = pg::new_pool(&args.pg_url, 1).expect("unable to create pg pool");
+    let mut pg = be_pool.get().await.expect("unable to get pg from pool");
+    let fe_pool = pg::new_pool(&args.pg_url_fe, 1).expect("unable to create fe pg pool");
+    let config = sync::RemoteConfig::load(&fe_pool)
+        .await
+        .expect("loading config")
+        .into_iter()
+        .find(|c| c.chain == args.chain)
+        .expect("unable to find chain");
+    match args.command {
+        Command::Export { from, to, dir } => {
+            let row = pg
+                .query_one(
+                    "select min(num), max(num) from blocks where chain = $1",
+                    &[&U64::from(args.chain)],
+                )
+                .await
+                .expect("querying block range");
+            let (from, to) = match (
+                row.get::<usize, Option<i64>>(0),
+                row.get::<usize, Option<i64>>(1),
+            ) {
+                (Some(min), Some(max)) => (from.unwrap_or(min as u64), to.unwrap_or(max as u64)),
+                _ => {
+                    println!("no blocks for chain {}", args.chain);
+                    return;
+                }
+            };
+            let adapter = config.filter.adapter;
+            let [blocks, txs, logs] = snapshot::export(&pg, args.chain, adapter, from, to, &dir)
+                .await
+                .expect("exporting snapshot");
+            println!("exported {from}..={to} blocks: {blocks} txs: {txs} logs: {logs}");
+        }
+        Command::Import { dir } => {
+            pg.batch_execute(SCHEMA_BE)
+                .await
+                .expect("updating backend schema");
+            let (from, to) = snapshot::import(
+                &mut pg,
+                config.chain,
+                config.filter.adapter,
+                config.partition_blocks,
+                &dir,
+            )
+            .await
+            .expect("importing snapshot");
+            println!("imported {from}..={to}");
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use alloy::primitives::U64;
+use be::{leader, sync, verify};
+use clap::Parser;
+use shared::pg;
+
+#[derive(Parser)]
+struct Args {
+    #[arg(env = "PG_URL", default_value = "postgres://localhost/be")]
+    pg_url: String,
+    #[arg(env = "PG_URL_FE", default_value = "postgres://localhost/fe")]
+    pg_url_fe: String,
+    #[arg(long = "chain")]
+    chain: u64,
+    /// Defaults to the earliest local block
+    #[arg(long = "from")]
+    from: Option<u64>,
+    /// Defaults to the latest local block
+    #[arg(long = "to")]
+    to: Option<u64>,
+    #[arg(long = "step", default_value = "1000")]
+    step: u64,
+    /// Re-download blocks that don't verify
+    #[clap(short = 'r', long = "repair", action = clap::ArgAction::SetTrue)]
+    repair: bool,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let fe_pool = pg::new_pool(&args.pg_url_fe, 1).expect("unable to create fe pg pool");
+    let be_pool = pg::new_pool(&args.pg_url, 2).expect("unable to create pg pool");
+    let mut pg = be_pool.get().await.expect("unable to get pg from pool");
+    let config = sync::RemoteConfig::load(&fe_pool)
+        .await
+        .expect("loading config")
+        .iter()
+        .find(|c| c.chain == args.chain)
+        .cloned()
+        .expect("unable to find chain");
+    let client = config.jrpc_client();
+    // stops the chain's sync from writing the blocks being repaired
+    let lock = match args.repair {
+        false => None,
+        true => match leader::Lock::try_acquire(&be_pool, args.chain)
+            .await
+            .expect("locking chain")
+        {
+            Some(lock) => Some(lock),
+            None => {
+                println!(
+                    "chain {} is locked by another process. stop it first",
+                    args.chain
+                );
+                return;
+            }
+        },
+    };
+
+    let row = pg
+        .query_one(
+            "select min(num), max(num) from blocks where chain = $1",
+            &[&U64::from(args.chain)],
+        )
+        .await
+        .expect("querying block range");
+    let (from, to) = match (
+        row.get::<usize, Option<i64>>(0),
+        row.get::<usize, Option<i64>>(1),
+    ) {
+        (Some(min), Some(max)) => (
+            args.from.unwrap_or(min as u64),
+            args.to.unwrap_or(max as u64),
+        ),
+        _ => {
+            println!("no blocks for chain {}", args.chain);
+            return;
+        }
+    };
+    let mut total = 0;
+    for start in (from..=to).step_by(args.step.max(1) as usize) {
+        let end = to.min(start + args.step.max(1) - 1);
+        let mismatches = verify::verify(&pg, args.chain, &config.filter, start, end)
+            .await
+            .expect("verifying blocks");
+        for m in mismatches.iter() {
+            println!("{m}");
+        }
+        total += mismatches.len();
+        let Some(lock) = &lock else {
+            continue;
+        };
+        let repaired = verify::repair(
+            &mut pg,
+            &client,
+            lock,
+            args.chain,
+            &config.filter,
+            &mismatches,
+        )
+        .await
+        .expect("sync failed");
+        for n in repaired {
+            println!("downloaded block {n}");
+        }
+    }
+    println!("verified {from}..={to} mismatches: {total}");
+}
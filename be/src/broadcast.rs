@@ -1,10 +1,37 @@
-use dashmap::DashMap;
+use std::{sync::Arc, time::Duration};
+
+use dashmap::{DashMap, DashSet};
+use eyre::{Context, Result};
 use futures_util::stream::{FuturesUnordered, StreamExt};
+use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_postgres::Transaction;
+
+/// Postgres channel on which sync publishes committed blocks
+/// for api processes that aren't syncing.
+const PG_CHANNEL: &str = "blocks";
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct NewBlock {
+    chain: u64,
+    num: u64,
+}
+
+/// Delivered to listeners when pgtx commits
+pub async fn notify(pgtx: &Transaction<'_>, chain: u64, num: u64) -> Result<()> {
+    let payload = serde_json::to_string(&NewBlock { chain, num })?;
+    pgtx.execute("select pg_notify($1, $2)", &[&PG_CHANNEL, &payload])
+        .await
+        .wrap_err("notifying new block")?;
+    Ok(())
+}
 
 pub struct Channel {
     pub json_updates: broadcast::Sender<serde_json::Value>,
     pub block_updates: DashMap<u64, broadcast::Sender<()>>,
+    // chains synced by this process. their sync task
+    // already updates the channel so notifications are skipped.
+    local: DashSet<u64>,
 }
 
 impl Default for Channel {
@@ -12,11 +39,32 @@ impl Default for Channel {
         Self {
             json_updates: broadcast::channel(16).0,
             block_updates: DashMap::new(),
+            local: DashSet::new(),
         }
     }
 }
 
+/// Marks a chain as synced by this process until dropped
+pub struct Local {
+    channel: Arc<Channel>,
+    chain: u64,
+}
+
+impl Drop for Local {
+    fn drop(&mut self) {
+        self.channel.local.remove(&self.chain);
+    }
+}
+
 impl Channel {
+    pub fn local(self: &Arc<Self>, chain: u64) -> Local {
+        self.local.insert(chain);
+        Local {
+            channel: self.clone(),
+            chain,
+        }
+    }
+
     fn subscribe(&self, chain_ids: &[u64]) -> Vec<(u64, broadcast::Receiver<()>)> {
         chain_ids
             .iter()
@@ -39,6 +87,29 @@ impl Channel {
         let _ = sender.send(());
     }
 
+    /// Feeds blocks committed by other processes into the channel.
+    /// url must be the primary since replicas don't relay notifications.
+    /// Chains marked local are skipped.
+    pub async fn listen(&self, url: &str) {
+        let mut listener = shared::pg::Listener::new(url, &[PG_CHANNEL]);
+        loop {
+            for payload in listener.wait(Duration::from_secs(60)).await {
+                match serde_json::from_str::<NewBlock>(&payload) {
+                    Ok(block) if self.local.contains(&block.chain) => {}
+                    Ok(block) => {
+                        self.update(block.chain);
+                        let _ = self.json_updates.send(serde_json::json!({
+                            "new_block": "local",
+                            "chain": block.chain,
+                            "num": block.num,
+                        }));
+                    }
+                    Err(e) => tracing::error!("decoding block notification {}: {}", payload, e),
+                }
+            }
+        }
+    }
+
     pub async fn wait(&self, chain_ids: &[u64]) -> Option<u64> {
         let mut futs = self
             .subscribe(chain_ids)
@@ -62,3 +133,38 @@ impl Channel {
         futs.next().await.and_then(|res| res.ok().flatten())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{notify, Channel};
+
+    #[tokio::test]
+    async fn test_listen() {
+        let url = "postgres://golden_axe@localhost:5432/golden_axe_test";
+        let channel = Arc::new(Channel::default());
+        let listening = channel.clone();
+        tokio::spawn(async move { listening.listen(url).await });
+        let local = channel.local(7);
+        let waiting = channel.clone();
+        let woken = tokio::spawn(async move { waiting.wait(&[7, 42]).await });
+
+        let pool = shared::pg::new_pool(url, 1).unwrap();
+        // notifications sent before the listener connects are missed
+        for _ in 0..100 {
+            let mut pg = pool.get().await.unwrap();
+            let pgtx = pg.transaction().await.unwrap();
+            notify(&pgtx, 7, 1).await.unwrap();
+            notify(&pgtx, 42, 1).await.unwrap();
+            pgtx.commit().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            if woken.is_finished() {
+                break;
+            }
+        }
+        assert_eq!(woken.await.unwrap(), Some(42));
+        drop(local);
+        assert!(channel.local.is_empty());
+    }
+}
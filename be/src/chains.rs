@@ -0,0 +1,160 @@
+use alloy::primitives::U64;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use crate::{api, partition};
+
+/// Sync status of a configured chain. The remote head, batch size and
+/// last error are recorded by the process syncing the chain every few
+/// seconds. updated_at is when they were last recorded and is null
+/// until the chain has been synced.
+#[derive(Debug, Default, Serialize)]
+pub struct Status {
+    pub chain: u64,
+    pub name: Option<String>,
+    pub enabled: bool,
+    pub local_num: Option<u64>,
+    pub remote_num: Option<u64>,
+    pub lag_blocks: Option<u64>,
+    pub lag_seconds: Option<u64>,
+    pub batch_size: Option<u16>,
+    pub last_error: Option<String>,
+    /// unix seconds
+    pub last_error_at: Option<i64>,
+    /// unix seconds
+    pub updated_at: Option<i64>,
+    pub partitions: Option<Partitions>,
+}
+
+/// First and last block (inclusive) covered by the chain's partitions
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Partitions {
+    pub count: usize,
+    pub from: u64,
+    pub to: u64,
+}
+
+pub async fn handle_list(
+    State(config): State<api::Config>,
+) -> Result<axum::Json<Vec<Status>>, api::Error> {
+    let mut statuses = vec![];
+    for status in configured(&config, None).await? {
+        statuses.push(load(&config, status).await?);
+    }
+    Ok(axum::Json(statuses))
+}
+
+pub async fn handle_get(
+    State(config): State<api::Config>,
+    Path(chain): Path<u64>,
+) -> Result<Response, api::Error> {
+    match configured(&config, Some(chain)).await?.pop() {
+        Some(status) => Ok(axum::Json(load(&config, status).await?).into_response()),
+        None => Ok((
+            StatusCode::NOT_FOUND,
+            axum::Json(api::ErrorMessage {
+                message: format!("chain {chain} not found"),
+            }),
+        )
+            .into_response()),
+    }
+}
+
+async fn configured(config: &api::Config, chain: Option<u64>) -> Result<Vec<Status>, api::Error> {
+    Ok(config
+        .fe_pool
+        .get()
+        .await?
+        .query(
+            "
+            select chain, name, coalesce(enabled, false) as enabled
+            from config
+            where $1::int8 is null or chain = $1
+            order by chain
+            ",
+            &[&chain.map(|c| c as i64)],
+        )
+        .await?
+        .iter()
+        .map(|row| Status {
+            chain: row.get::<&str, U64>("chain").to(),
+            name: row.get("name"),
+            enabled: row.get("enabled"),
+            ..Default::default()
+        })
+        .collect())
+}
+
+async fn load(config: &api::Config, mut status: Status) -> Result<Status, api::Error> {
+    let chain = status.chain as i64;
+    let mut pg = config.be_pool.get().await?;
+    let pgtx = pg.build_transaction().read_only(true).start().await?;
+    let local = pgtx
+        .query_opt(
+            "
+            select num, extract(epoch from timestamp)::int8 as timestamp
+            from blocks
+            where chain = $1
+            order by num desc
+            limit 1
+            ",
+            &[&chain],
+        )
+        .await?
+        .map(|row| {
+            (
+                row.get::<&str, i64>("num"),
+                row.get::<&str, i64>("timestamp"),
+            )
+        });
+    let remote = pgtx
+        .query_opt(
+            "
+            select
+                remote_num,
+                extract(epoch from remote_timestamp)::int8 as remote_timestamp,
+                batch_size,
+                last_error,
+                extract(epoch from last_error_at)::int8 as last_error_at,
+                extract(epoch from updated_at)::int8 as updated_at
+            from chain_state
+            where chain = $1
+            ",
+            &[&chain],
+        )
+        .await?;
+    let partitions = partition::list(&pgtx, &format!("blocks_c{chain}"))
+        .await
+        .map_err(|e| api::Error::Server(format!("listing partitions: {e:?}").into()))?;
+
+    status.local_num = local.map(|(num, _)| num as u64);
+    status.partitions = match (partitions.first(), partitions.last()) {
+        (Some(first), Some(last)) => Some(Partitions {
+            count: partitions.len(),
+            from: first.from,
+            to: last.to - 1,
+        }),
+        _ => None,
+    };
+    if let Some(row) = remote {
+        let remote_num = row.get::<&str, Option<i64>>("remote_num");
+        let remote_timestamp = row.get::<&str, Option<i64>>("remote_timestamp");
+        status.remote_num = remote_num.map(|n| n as u64);
+        status.batch_size = row.get::<&str, Option<i32>>("batch_size").map(|n| n as u16);
+        status.last_error = row.get("last_error");
+        status.last_error_at = row.get("last_error_at");
+        status.updated_at = row.get("updated_at");
+        if let (Some((local_num, local_timestamp)), Some(remote_num), Some(remote_timestamp)) =
+            (local, remote_num, remote_timestamp)
+        {
+            status.lag_blocks = Some(remote_num.saturating_sub(local_num).max(0) as u64);
+            status.lag_seconds =
+                Some(remote_timestamp.saturating_sub(local_timestamp).max(0) as u64);
+        }
+    }
+    Ok(status)
+}
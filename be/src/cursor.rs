@@ -3,6 +3,7 @@ use std::{
     str::FromStr,
 };
 
+use alloy::primitives::U64;
 use eyre::Context;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
@@ -10,13 +11,13 @@ use serde::{Deserialize, Serialize};
 use crate::api;
 
 #[derive(Clone, Debug, Default)]
-pub struct Cursor(HashMap<u64, Option<u64>>);
+pub struct Cursor(HashMap<u64, Option<u64>>, HashMap<u64, u64>);
 
 impl Cursor {
     pub fn new(chain: u64, block_num: Option<u64>) -> Self {
         let mut map = HashMap::new();
         map.insert(chain, block_num);
-        Cursor(map)
+        Cursor(map, HashMap::new())
     }
 
     pub fn add_chains(&mut self, chains: &HashSet<u64>) {
@@ -25,6 +26,20 @@ impl Cursor {
         })
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Limits the query to blocks less than or equal to n.
+    /// The limit is not part of the serialized cursor.
+    pub fn set_block_limit(&mut self, chain: u64, n: u64) {
+        self.1.insert(chain, n);
+    }
+
+    pub fn block_limit(&self, chain: u64) -> Option<u64> {
+        self.1.get(&chain).copied()
+    }
+
     pub fn contains(&self, chain: u64) -> bool {
         self.0.keys().any(|c| *c == chain)
     }
@@ -44,14 +59,26 @@ impl Cursor {
         self.0.insert(chain, Some(n));
     }
 
+    /// Predicate for tables that aren't keyed by block (eg tokens)
+    pub fn chains_sql(&self) -> String {
+        match self.chains().as_slice() {
+            [chain] => format!("chain = {chain}"),
+            chains => format!("chain in ({})", chains.iter().join(", ")),
+        }
+    }
+
     pub fn to_sql(&self, col_name: &str) -> String {
         let predicates = self
             .0
             .iter()
             .sorted_by_key(|(chain, _)| *chain)
-            .map(|(chain, block_num)| match block_num {
-                Some(n) => format!("(chain = {chain} and {col_name} >= {n})"),
-                None => format!("chain = {chain}"),
+            .map(|(chain, block_num)| match (block_num, self.1.get(chain)) {
+                (Some(n), Some(m)) => {
+                    format!("(chain = {chain} and {col_name} >= {n} and {col_name} <= {m})")
+                }
+                (Some(n), None) => format!("(chain = {chain} and {col_name} >= {n})"),
+                (None, Some(m)) => format!("(chain = {chain} and {col_name} <= {m})"),
+                (None, None) => format!("chain = {chain}"),
             })
             .collect::<Vec<_>>();
         if predicates.len() == 1 {
@@ -125,3 +152,110 @@ impl Serialize for Cursor {
         serializer.serialize_str(&encoded)
     }
 }
+
+/// Safe and finalized block numbers as reported by the chain's RPC.
+/// Either may be missing when the chain doesn't support the block tag.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Finality {
+    pub safe: Option<u64>,
+    pub finalized: Option<u64>,
+}
+
+impl Finality {
+    pub async fn load(
+        pgtx: &tokio_postgres::Transaction<'_>,
+    ) -> Result<HashMap<u64, Finality>, api::Error> {
+        Ok(pgtx
+            .query("select chain, safe, finalized from chain_state", &[])
+            .await?
+            .iter()
+            .map(|row| {
+                (
+                    row.get::<&str, U64>("chain").to(),
+                    Finality {
+                        safe: row.get::<&str, Option<U64>>("safe").map(|n| n.to()),
+                        finalized: row.get::<&str, Option<U64>>("finalized").map(|n| n.to()),
+                    },
+                )
+            })
+            .collect())
+    }
+
+    /// Caps the cursor at the finalized block of every chain that has one.
+    /// Must be called before the query is rendered since the query's
+    /// chains aren't known until it is parsed.
+    pub fn limit(cursor: &mut Cursor, finality: &HashMap<u64, Finality>) {
+        for (chain, f) in finality {
+            if let Some(n) = f.finalized {
+                cursor.set_block_limit(*chain, n);
+            }
+        }
+    }
+
+    /// Errors when one of the cursor's chains wasn't capped by limit.
+    pub fn check(cursor: &Cursor) -> Result<(), api::Error> {
+        match cursor
+            .chains()
+            .into_iter()
+            .find(|c| cursor.block_limit(*c).is_none())
+        {
+            Some(chain) => Err(api::Error::User(format!(
+                "finalized block not available for chain {chain}"
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the safe and finalized block numbers of the
+    /// provided chains encoded as cursors. Like the query's cursor
+    /// they point at the block after, so a cursor equal to
+    /// the finalized cursor has seen every finalized block.
+    pub fn cursors(chains: &[u64], finality: &HashMap<u64, Finality>) -> (Cursor, Cursor) {
+        let (mut safe, mut finalized) = (Cursor::default(), Cursor::default());
+        for (chain, f) in chains.iter().filter_map(|c| Some((*c, finality.get(c)?))) {
+            if let Some(n) = f.safe {
+                safe.set_block_height(chain, n + 1);
+            }
+            if let Some(n) = f.finalized {
+                finalized.set_block_height(chain, n + 1);
+            }
+        }
+        (safe, finalized)
+    }
+}
+
+/// Chains that are synced with a filter and only store some of their data
+pub async fn partial_chains(
+    pgtx: &tokio_postgres::Transaction<'_>,
+) -> Result<HashSet<u64>, api::Error> {
+    Ok(pgtx
+        .query("select chain from chain_state where partial", &[])
+        .await?
+        .iter()
+        .map(|row| row.get::<&str, U64>("chain").to())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{Cursor, Finality};
+
+    #[test]
+    fn test_finality_cursors() {
+        let finality = HashMap::from([(
+            1,
+            Finality {
+                safe: Some(110),
+                finalized: Some(100),
+            },
+        )]);
+        // the query's cursor when the latest block is 100
+        let mut latest = Cursor::default();
+        latest.set_block_height(1, 101);
+        let (safe, finalized) = Finality::cursors(&[1], &finality);
+        assert_eq!(finalized.to_string(), latest.to_string());
+        assert_eq!(safe.to_string(), "1-111");
+    }
+}
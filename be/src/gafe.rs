@@ -44,6 +44,7 @@ impl AccountLimitSnapshot {
 #[derive(Debug)]
 pub struct AccountLimit {
     secret: String,
+    pub plan: String,
     pub origins: HashSet<String>,
     pub timeout: Duration,
     pub rate: i32,
@@ -57,6 +58,7 @@ pub struct AccountLimit {
 impl PartialEq for AccountLimit {
     fn eq(&self, other: &Self) -> bool {
         self.secret == other.secret
+            && self.plan == other.plan
             && self.origins == other.origins
             && self.timeout == other.timeout
             && self.rate == other.rate
@@ -69,6 +71,7 @@ impl AccountLimit {
     pub fn free() -> Self {
         AccountLimit {
             secret: String::default(),
+            plan: String::from("free"),
             origins: HashSet::new(),
             timeout: Duration::from_secs(10),
             rate: 10,
@@ -85,6 +88,7 @@ impl AccountLimit {
     pub fn open() -> Self {
         AccountLimit {
             secret: String::default(),
+            plan: String::from("open"),
             origins: HashSet::new(),
             timeout: Duration::from_secs(10),
             rate: 10,
@@ -156,7 +160,7 @@ impl Connection {
             })
             .ok()?
             .query(
-                "select secret, timeout, rate, connections, ip_connections, origins from account_limits",
+                "select secret, plan, timeout, rate, connections, ip_connections, origins from account_limits",
                 &[],
             )
             .await
@@ -169,6 +173,7 @@ impl Connection {
             res.iter()
                 .map(|row| AccountLimit {
                     secret: row.get("secret"),
+                    plan: row.get("plan"),
                     timeout: Duration::from_secs(row.get::<&str, i32>("timeout") as u64),
                     origins: row
                         .get::<&str, Vec<String>>("origins")
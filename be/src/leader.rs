@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use deadpool_postgres::{ClientWrapper, Object, Pool};
+use eyre::{eyre, Context, Result};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+// a half open connection never errors so checks give up after this
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+// the first key of the two key advisory lock so that chain
+// ids don't collide with other users of the single key space
+const NAMESPACE: i32 = 0x7379_6e63; // "sync"
+
+/// A session level advisory lock keyed by (NAMESPACE, chain). The lock's
+/// connection is taken out of the pool so that it's closed, and the lock
+/// released, when the Lock is dropped or when the process dies. A standby
+/// waiting in acquire takes over within POLL_INTERVAL.
+/// Chain ids above i32::MAX wrap, which only matters when two such
+/// chains share a database.
+pub struct Lock {
+    pub chain: u64,
+    client: ClientWrapper,
+}
+
+impl Lock {
+    pub async fn try_acquire(pool: &Pool, chain: u64) -> Result<Option<Lock>> {
+        let client = pool.get().await.wrap_err("pg pool")?;
+        let locked: bool = client
+            .query_one(
+                "select pg_try_advisory_lock($1, $2)",
+                &[&NAMESPACE, &(chain as i32)],
+            )
+            .await
+            .wrap_err("locking")?
+            .get(0);
+        Ok(locked.then(|| Lock {
+            chain,
+            client: Object::take(client),
+        }))
+    }
+
+    /// Waits until no other process holds the chain's lock
+    pub async fn acquire(pool: &Pool, chain: u64) -> Result<Lock> {
+        let mut waiting = false;
+        loop {
+            if let Some(lock) = Self::try_acquire(pool, chain).await? {
+                return Ok(lock);
+            }
+            if !waiting {
+                tracing::info!("standby for chain {}", chain);
+                waiting = true;
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Errors unless the lock's connection answers within CHECK_TIMEOUT.
+    /// Writers call this before committing so that a process that
+    /// lost its lock stops writing before its standby starts.
+    pub async fn check(&self) -> Result<()> {
+        tokio::time::timeout(CHECK_TIMEOUT, self.client.simple_query("select 1"))
+            .await
+            .map_err(|_| eyre!("lock for chain {} timed out", self.chain))?
+            .wrap_err_with(|| format!("lock for chain {}", self.chain))?;
+        Ok(())
+    }
+
+    /// Returns when the lock's connection is closed or stops answering.
+    /// The lock is released with it so another process may be syncing.
+    pub async fn lost(&self) {
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            if let Err(e) = self.check().await {
+                tracing::error!("lost lock: {:?}", e);
+                return;
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Lock;
+
+    static SCHEMA: &str = include_str!("./sql/schema.sql");
+
+    #[tokio::test]
+    async fn test_lock() {
+        let pool = shared::pg::test::new(SCHEMA).await;
+        let lock = Lock::try_acquire(&pool, 1).await.unwrap().unwrap();
+        assert!(Lock::try_acquire(&pool, 1).await.unwrap().is_none());
+        assert!(Lock::try_acquire(&pool, 2).await.unwrap().is_some());
+        lock.check().await.unwrap();
+        // the single key space is left to others
+        let pg = pool.get().await.unwrap();
+        let locked: bool = pg
+            .query_one("select pg_try_advisory_lock(1)", &[])
+            .await
+            .unwrap()
+            .get(0);
+        assert!(locked);
+        drop(lock);
+        // the lock is released once postgres sees the connection close
+        for _ in 0..50 {
+            if Lock::try_acquire(&pool, 1).await.unwrap().is_some() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("lock wasn't released");
+    }
+}
@@ -1,11 +1,20 @@
 pub mod abi;
+pub mod adapter;
 pub mod api;
 pub mod api_sql;
 pub mod api_sql2;
 pub mod broadcast;
+pub mod chains;
 pub mod cursor;
 pub mod gafe;
+pub mod leader;
+pub mod metrics;
+pub mod offline;
+pub mod partition;
 pub mod query;
 pub mod s256;
+pub mod snapshot;
 pub mod sync;
+pub mod tokens;
 pub mod user_query;
+pub mod verify;
@@ -1,4 +1,4 @@
-use std::{net::SocketAddr, time::Duration};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
 use axum::{
     body::Body,
@@ -6,8 +6,8 @@ use axum::{
     extract::{connect_info::IntoMakeServiceWithConnectInfo, MatchedPath},
     routing::{get, post, Router},
 };
-use be::{api, api_sql, api_sql2, sync, user_query};
-use clap::Parser;
+use be::{api, api_sql, api_sql2, chains, metrics, sync, user_query};
+use clap::{Parser, Subcommand};
 use tower::ServiceBuilder;
 use tower_http::{
     classify::ServerErrorsFailureClass, compression::CompressionLayer, cors::CorsLayer,
@@ -53,6 +53,23 @@ struct Args {
 
     #[clap(env = "ADMIN_API_SECRET", default_value = "foo")]
     admin_api_secret: String,
+
+    #[arg(long = "archive-dir", env = "ARCHIVE_DIR")]
+    archive_dir: Option<PathBuf>,
+
+    /// Defaults to running both the api and sync
+    #[command(subcommand)]
+    mode: Option<Mode>,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum Mode {
+    /// Download chains. Only serves /metrics.
+    /// Each chain is downloaded by one sync process at a time
+    /// and standby processes take over when it exits.
+    Sync,
+    /// Serve the api without downloading chains
+    Api,
 }
 
 static SCHEMA_BE: &str = include_str!("./sql/schema.sql");
@@ -71,12 +88,15 @@ async fn main() {
         .init();
 
     let args = Args::parse();
-    let config = api::Config::new(
+    let mut config = api::Config::new(
         args.admin_api_secret.to_string(),
         shared::pg::new_pool(&args.pg_url, args.max_pg_conns.unwrap_or(32)).expect("pg_be pool"),
         shared::pg::new_pool(&args.pg_url_fe, args.max_pg_fe_conns).expect("pg_fe pool"),
         shared::pg::new_pool(&args.pg_url_ro, args.max_pg_conns.unwrap_or(32)).expect("pg_ro pool"),
     );
+    config.archive_dir = args.archive_dir.clone();
+    config.fe_url = Some(args.pg_url_fe.clone());
+    config.metrics = Some(metrics::install());
     config
         .be_pool
         .get()
@@ -90,24 +110,42 @@ async fn main() {
         .await
         .expect("binding to tcp for http server");
 
-    tokio::spawn(account_limits(config.clone()));
-    tokio::spawn(stats_updates(config.clone()));
-    tokio::spawn(sync(args.clone(), config.clone()));
-    axum::serve(listener, service(config.clone()))
-        .await
-        .expect("serving api");
+    let (serve_api, run_sync) = match args.mode {
+        None => (true, !args.no_sync),
+        Some(Mode::Api) => (true, false),
+        Some(Mode::Sync) => (false, true),
+    };
+    if run_sync {
+        tokio::spawn(sync(config.clone()));
+    }
+    if serve_api {
+        let (config, url) = (config.clone(), args.pg_url.clone());
+        tokio::spawn(async move { config.broadcaster.listen(&url).await });
+    }
+    if serve_api {
+        tokio::spawn(account_limits(config.clone()));
+        tokio::spawn(stats_updates(config.clone()));
+        axum::serve(listener, service(config.clone()))
+            .await
+            .expect("serving api");
+    } else {
+        let metrics = Router::new()
+            .route("/metrics", get(metrics::handle_metrics))
+            .with_state(config.clone());
+        axum::serve(listener, metrics)
+            .await
+            .expect("serving metrics");
+    }
 }
 
-async fn sync(args: Args, config: api::Config) {
-    if !args.no_sync {
-        loop {
-            let config = config.clone();
-            let result = tokio::spawn(sync::run(config.clone())).await;
-            if let Err(e) = result {
-                tracing::error!("sync error: {}", e);
-            }
-            tokio::time::sleep(Duration::from_secs(10)).await;
+async fn sync(config: api::Config) {
+    loop {
+        let config = config.clone();
+        let result = tokio::spawn(sync::run(config.clone())).await;
+        if let Err(e) = result {
+            tracing::error!("sync error: {}", e);
         }
+        tokio::time::sleep(Duration::from_secs(10)).await;
     }
 }
 
@@ -143,6 +181,10 @@ async fn stats_updates(config: api::Config) {
 }
 
 async fn account_limits(config: api::Config) {
+    let mut listener = config
+        .fe_url
+        .as_deref()
+        .map(|url| shared::pg::Listener::new(url, &["account_limits"]));
     loop {
         let config = config.clone();
         let result = tokio::spawn(async move {
@@ -163,7 +205,12 @@ async fn account_limits(config: api::Config) {
         if let Err(e) = result {
             tracing::error!("account_limits error: {}", e);
         }
-        tokio::time::sleep(Duration::from_secs(10)).await;
+        match listener.as_mut() {
+            Some(listener) => {
+                listener.wait(Duration::from_secs(60)).await;
+            }
+            None => tokio::time::sleep(Duration::from_secs(10)).await,
+        }
     }
 }
 
@@ -199,6 +246,10 @@ fn service(config: api::Config) -> IntoMakeServiceWithConnectInfo<Router, Socket
         );
     let service = ServiceBuilder::new()
         .layer(axum::middleware::from_fn(api::latency_header))
+        .layer(axum::middleware::from_fn_with_state(
+            config.clone(),
+            metrics::record_request,
+        ))
         .layer(tracing)
         .layer(axum::middleware::from_fn(api::log_fields))
         .layer(axum::middleware::from_fn(api::content_length_header))
@@ -219,6 +270,9 @@ fn service(config: api::Config) -> IntoMakeServiceWithConnectInfo<Router, Socket
         .route("/", get(|| async { "hello\n" }))
         .route("/status", get(api::handle_status))
         .route("/conns", get(api::handle_conns))
+        .route("/chains", get(chains::handle_list))
+        .route("/chains/:chain", get(chains::handle_get))
+        .route("/metrics", get(metrics::handle_metrics))
         .route("/query", get(api_sql::handle_get))
         .route("/query", post(api_sql::handle_post))
         .route("/query-live", get(api_sql::handle_sse))
@@ -243,8 +297,9 @@ mod tests {
     use super::service;
     use super::SCHEMA_BE;
     use be::{
+        adapter,
         api::{self},
-        api_sql, sync,
+        api_sql, api_sql2, cursor, metrics, partition, snapshot, sync,
     };
     use shared::jrpc;
 
@@ -267,6 +322,11 @@ mod tests {
                 state_root: B256::with_last_byte(0x01),
                 extra_data: Bytes::from(vec![]),
                 miner: Address::with_last_byte(0x01),
+                base_fee_per_gas: None,
+                blob_gas_used: None,
+                excess_blob_gas: None,
+                withdrawals_root: None,
+                withdrawals: vec![],
             };
             let log = jrpc::Log {
                 data: log_data.data,
@@ -285,13 +345,14 @@ mod tests {
                 .transaction()
                 .await
                 .expect("unable to start new pgtx from pg pool");
-            sync::setup_tables(&pgtx, $chain.0, block.number.to(), None)
+            let n = block.number.to();
+            sync::setup_tables(&pgtx, $chain.0, partition::DEFAULT_BLOCKS, n, n)
                 .await
                 .expect("setting up tables");
-            sync::copy_logs(&pgtx, $chain, vec![log])
+            sync::copy_logs(&pgtx, $chain, vec![log], sync::CopyMode::Merge)
                 .await
                 .expect("unable to copy new logs");
-            sync::copy_blocks(&pgtx, $chain, &[block])
+            sync::copy_blocks(&pgtx, $chain, &[block], sync::CopyMode::Merge)
                 .await
                 .expect("copying blocks");
             pgtx.commit()
@@ -308,6 +369,149 @@ mod tests {
         server.get("/").await.assert_text_contains("hello");
     }
 
+    #[tokio::test]
+    async fn test_copy_twice() {
+        let pool = shared::pg::test::new(SCHEMA_BE).await;
+        sol! {
+            #[sol(abi)]
+            event Foo(uint a);
+        };
+        add_log!(pool, api::Chain(1), U64::from(1), Foo { a: U256::from(42) });
+        add_log!(pool, api::Chain(1), U64::from(1), Foo { a: U256::from(42) });
+        let row = pool
+            .get()
+            .await
+            .unwrap()
+            .query_one(
+                "select (select count(*) from blocks), (select count(*) from logs)",
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!((row.get::<usize, i64>(0), row.get::<usize, i64>(1)), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_setup_tables_existing_range() {
+        let pool = shared::pg::test::new(SCHEMA_BE).await;
+        let mut pg = pool.get().await.unwrap();
+        // a range created before traces and withdrawals existed
+        pg.batch_execute(
+            "
+            create table blocks_c1 partition of blocks for values in (1) partition by range (num);
+            create table blocks_c1_b0 partition of blocks_c1 for values from (0) to (2000000);
+            ",
+        )
+        .await
+        .unwrap();
+        let pgtx = pg.transaction().await.unwrap();
+        let partitions = sync::setup_tables(&pgtx, 1, partition::DEFAULT_BLOCKS, 10, 10)
+            .await
+            .unwrap();
+        assert_eq!(partitions.len(), 1);
+        for table in partition::TABLES {
+            let got = partition::list(&pgtx, &format!("{table}_c1"))
+                .await
+                .unwrap();
+            assert_eq!(got.len(), 1, "{table}");
+            assert_eq!((got[0].from, got[0].to), (0, 2000000), "{table}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_setup_tables_missing_unique_index() {
+        let pool = shared::pg::test::new(SCHEMA_BE).await;
+        let mut pg = pool.get().await.unwrap();
+        // a chain created before schema.sql had the unique indexes
+        pg.batch_execute(
+            "
+            drop index logs_chain_block_log_idx;
+            create table logs_c1 partition of logs for values in (1) partition by range (block_num);
+            create unique index logs_chain_block_log_idx on only logs(chain, block_num, log_idx);
+            ",
+        )
+        .await
+        .unwrap();
+        let pgtx = pg.transaction().await.unwrap();
+        let err = sync::setup_tables(&pgtx, 1, partition::DEFAULT_BLOCKS, 10, 10)
+            .await
+            .unwrap_err();
+        assert!(format!("{err:?}").contains("logs_c1 is missing unique index"));
+        drop(pgtx);
+        let pgtx = pg.transaction().await.unwrap();
+        sync::setup_tables(&pgtx, 2, partition::DEFAULT_BLOCKS, 10, 10)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let pool = shared::pg::test::new(SCHEMA_BE).await;
+        let mut config = api::Config::new(String::new(), pool.clone(), pool.clone(), pool.clone());
+        config.metrics = Some(metrics::install());
+        let server = TestServer::new(service(config)).unwrap();
+        server.get("/").await;
+        let resp = server.get("/metrics").await;
+        resp.assert_text_contains(
+            r#"api_request_duration_seconds_bucket{route="/",status="200",plan="free""#,
+        );
+        resp.assert_text_contains(r#"pg_pool_max_size{pool="be"}"#);
+    }
+
+    #[tokio::test]
+    async fn test_chains() {
+        let pool = shared::pg::test::new(SCHEMA_BE).await;
+        pool.get()
+            .await
+            .unwrap()
+            .batch_execute(
+                "
+                create table config (enabled bool, name text, chain int8 primary key);
+                insert into config values (true, 'One', 1), (false, 'Two', 2);
+                insert into chain_state(chain, remote_num, remote_timestamp, batch_size, last_error, last_error_at)
+                values (1, 11, to_timestamp(13), 100, 'boom', to_timestamp(5));
+                ",
+            )
+            .await
+            .unwrap();
+        sol! {
+            #[sol(abi)]
+            event Foo(uint a);
+        };
+        add_log!(pool, api::Chain(1), U64::from(1), Foo { a: U256::from(42) });
+
+        let config = api::Config::new(String::new(), pool.clone(), pool.clone(), pool.clone());
+        let server = TestServer::new(service(config)).unwrap();
+        let mut one = server.get("/chains/1").await.json::<serde_json::Value>();
+        assert!(one["updated_at"].is_i64());
+        one["updated_at"] = json!(null);
+        assert_eq!(
+            one,
+            json!({
+                "chain": 1,
+                "name": "One",
+                "enabled": true,
+                "local_num": 1,
+                "remote_num": 11,
+                "lag_blocks": 10,
+                "lag_seconds": 12,
+                "batch_size": 100,
+                "last_error": "boom",
+                "last_error_at": 5,
+                "updated_at": null,
+                "partitions": {"count": 1, "from": 0, "to": 1999999},
+            })
+        );
+
+        let all = server.get("/chains").await.json::<serde_json::Value>();
+        assert_eq!(all[1]["chain"], 2);
+        assert_eq!(all[1]["enabled"], false);
+        assert_eq!(all[1]["local_num"], json!(null));
+
+        let missing = server.get("/chains/3").await;
+        missing.assert_status_not_found();
+    }
+
     #[tokio::test]
     async fn test_query_post_with_params() {
         let pool = shared::pg::test::new(SCHEMA_BE).await;
@@ -323,6 +527,7 @@ mod tests {
             api_key: None,
             chain: None,
             block_height: None,
+            finalized_only: false,
             event_signatures: vec![Foo::abi().full_signature()],
             query: String::from("select a, block_num from foo"),
         }];
@@ -338,6 +543,145 @@ mod tests {
             }));
     }
 
+    #[tokio::test]
+    async fn test_query_partial() {
+        let pool = shared::pg::test::new(SCHEMA_BE).await;
+        sol! {
+            #[sol(abi)]
+            event Foo(uint a);
+        };
+        add_log!(pool, api::Chain(1), U64::from(1), Foo { a: U256::from(42) });
+        pool.get()
+            .await
+            .unwrap()
+            .execute(
+                "insert into chain_state(chain, partial) values (1, true)",
+                &[],
+            )
+            .await
+            .unwrap();
+
+        let config = api::Config::new(String::new(), pool.clone(), pool.clone(), pool.clone());
+        let server = TestServer::new(service(config)).unwrap();
+        let request = vec![api_sql2::Request {
+            api_key: None,
+            cursor: cursor::Cursor::new(1, None),
+            signatures: vec![Foo::abi().full_signature()],
+            query: String::from("select a from foo"),
+            finalized_only: false,
+        }];
+        server
+            .post("/v2/query")
+            .add_query_param("api-key", "face")
+            .json(&request)
+            .await
+            .assert_json(&json!([{
+                "cursor": "1-2",
+                "partial": [1],
+                "columns": [{"name": "a", "pgtype": "numeric"}],
+                "rows": [["42"]]
+            }]));
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let pool = shared::pg::test::new(SCHEMA_BE).await;
+        sol! {
+            #[sol(abi)]
+            event Foo(uint a);
+        };
+        add_log!(pool, api::Chain(1), U64::from(1), Foo { a: U256::from(42) });
+        add_log!(pool, api::Chain(1), U64::from(2), Foo { a: U256::from(43) });
+        let dir = std::env::temp_dir().join(format!("snapshot-{}", std::process::id()));
+        let mut pg = pool.get().await.unwrap();
+        let counts = snapshot::export(&pg, 1, &adapter::Ethereum, 1, 2, &dir)
+            .await
+            .unwrap();
+        assert_eq!(counts, [2, 0, 2]);
+
+        pg.execute("delete from logs", &[]).await.unwrap();
+        pg.execute("delete from blocks", &[]).await.unwrap();
+        let err = snapshot::import(
+            &mut pg,
+            1,
+            &adapter::OpStack,
+            partition::DEFAULT_BLOCKS,
+            &dir,
+        )
+        .await
+        .unwrap_err();
+        assert!(format!("{err:?}").contains("snapshot is for adapter ethereum not op"));
+        let range = snapshot::import(
+            &mut pg,
+            1,
+            &adapter::Ethereum,
+            partition::DEFAULT_BLOCKS,
+            &dir,
+        )
+        .await
+        .unwrap();
+        assert_eq!(range, (1, 2));
+        let row = pg
+            .query_one(
+                "select (select count(*) from blocks), (select count(*) from logs)",
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!((row.get::<usize, i64>(0), row.get::<usize, i64>(1)), (2, 2));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_adapter() {
+        let pool = shared::pg::test::new(SCHEMA_BE).await;
+        sol! {
+            #[sol(abi)]
+            event Foo(uint a);
+        };
+        add_log!(pool, api::Chain(1), U64::from(1), Foo { a: U256::from(42) });
+        let mut pg = pool.get().await.unwrap();
+        pg.batch_execute(
+            r#"
+            insert into txs (
+                chain, block_num, block_timestamp, idx, type, gas, gas_price,
+                nonce, hash, "from", "to", input, value,
+                gas_used, cumulative_gas_used, source_hash, l1_fee
+            ) values (
+                1, 1, to_timestamp(1), 0, 126, 1, 0,
+                '\x00', decode(repeat('ab', 32), 'hex'),
+                decode(repeat('01', 20), 'hex'), decode(repeat('02', 20), 'hex'), '\x', 0,
+                1, 1, '\x0102', 42
+            );
+            "#,
+        )
+        .await
+        .unwrap();
+        let dir = std::env::temp_dir().join(format!("snapshot-op-{}", std::process::id()));
+        let counts = snapshot::export(&pg, 1, &adapter::OpStack, 1, 1, &dir)
+            .await
+            .unwrap();
+        assert_eq!(counts, [1, 1, 1]);
+
+        pg.execute("delete from txs", &[]).await.unwrap();
+        snapshot::import(
+            &mut pg,
+            1,
+            &adapter::OpStack,
+            partition::DEFAULT_BLOCKS,
+            &dir,
+        )
+        .await
+        .unwrap();
+        let row = pg
+            .query_one("select source_hash, l1_fee from txs", &[])
+            .await
+            .unwrap();
+        assert_eq!(row.get::<usize, Vec<u8>>(0), vec![1, 2]);
+        assert_eq!(row.get::<usize, U256>(1), U256::from(42));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
     #[tokio::test]
     async fn test_query_sse() {
         let pool = shared::pg::test::new(SCHEMA_BE).await;
@@ -352,6 +696,7 @@ mod tests {
             api_key: None,
             chain: Some(1),
             block_height: None,
+            finalized_only: false,
             event_signatures: vec![Foo::abi().full_signature()],
             query: String::from("select a, block_num from foo"),
         };
@@ -394,6 +739,7 @@ mod tests {
             api_key: None,
             chain: Some(1),
             block_height: None,
+            finalized_only: false,
             event_signatures: vec![Foo::abi().full_signature()],
             query: String::from("select a, block_num from bar"),
         };
@@ -0,0 +1,106 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::extract::{MatchedPath, State};
+use deadpool_postgres::Pool;
+use metrics::{counter, gauge, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+use crate::{api, gafe};
+
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+/// Installs the global recorder. Metrics recorded before this
+/// (or when it isn't called) are dropped.
+pub fn install() -> PrometheusHandle {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), LATENCY_BUCKETS)
+        .expect("setting histogram buckets")
+        .install_recorder()
+        .expect("installing metrics recorder");
+    let upkeep = handle.clone();
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(UPKEEP_INTERVAL).await;
+            upkeep.run_upkeep();
+        }
+    });
+    handle
+}
+
+pub async fn handle_metrics(State(config): State<api::Config>) -> String {
+    gauge!("api_active_connections").set(config.active_connections() as f64);
+    for (name, pool) in [
+        ("be", &config.be_pool),
+        ("ro", &config.ro_pool),
+        ("fe", &config.fe_pool),
+    ] {
+        pool_status(name, pool);
+    }
+    config
+        .metrics
+        .as_ref()
+        .map(PrometheusHandle::render)
+        .unwrap_or_default()
+}
+
+fn pool_status(name: &'static str, pool: &Pool) {
+    let status = pool.status();
+    gauge!("pg_pool_max_size", "pool" => name).set(status.max_size as f64);
+    gauge!("pg_pool_size", "pool" => name).set(status.size as f64);
+    gauge!("pg_pool_available", "pool" => name).set(status.available as f64);
+    gauge!("pg_pool_waiting", "pool" => name).set(status.waiting as f64);
+}
+
+pub async fn record_request(
+    path: Option<MatchedPath>,
+    account_limit: Arc<gafe::AccountLimit>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let start = tokio::time::Instant::now();
+    let response = next.run(request).await;
+    histogram!(
+        "api_request_duration_seconds",
+        "route" => path.map(|p| p.as_str().to_string()).unwrap_or_default(),
+        "status" => response.status().as_u16().to_string(),
+        "plan" => account_limit.plan.clone(),
+    )
+    .record(start.elapsed().as_secs_f64());
+    response
+}
+
+pub fn rate_limited(account_limit: &gafe::AccountLimit) {
+    counter!("api_rate_limited_total", "plan" => account_limit.plan.clone()).increment(1);
+}
+
+/// remote is None when the chain isn't synced from an rpc
+pub fn heads(chain: api::Chain, local: u64, remote: Option<u64>) {
+    let chain = chain.to_string();
+    gauge!("sync_local_head", "chain" => chain.clone()).set(local as f64);
+    if let Some(remote) = remote {
+        gauge!("sync_remote_head", "chain" => chain.clone()).set(remote as f64);
+        gauge!("sync_lag_blocks", "chain" => chain).set(remote.saturating_sub(local) as f64);
+    }
+}
+
+pub fn ingested(chain: api::Chain, blocks: u64, txs: u64, logs: u64) {
+    let chain = chain.to_string();
+    counter!("sync_blocks_total", "chain" => chain.clone()).increment(blocks);
+    counter!("sync_txs_total", "chain" => chain.clone()).increment(txs);
+    counter!("sync_logs_total", "chain" => chain).increment(logs);
+}
+
+/// Set while this process holds the chain's sync lock
+pub fn leader(chain: api::Chain, leader: bool) {
+    gauge!("sync_leader", "chain" => chain.to_string()).set(leader as u8 as f64);
+}
+
+pub fn reorg(chain: api::Chain, depth: u64) {
+    let chain = chain.to_string();
+    counter!("sync_reorgs_total", "chain" => chain.clone()).increment(1);
+    histogram!("sync_reorg_depth", "chain" => chain).record(depth as f64);
+}
@@ -0,0 +1,260 @@
+use std::{path::Path, sync::Arc, time::Duration};
+
+use alloy::primitives::{BlockHash, U64};
+use deadpool_postgres::Pool;
+use eyre::{eyre, Context};
+use serde::de::DeserializeOwned;
+use shared::jrpc;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::{
+    api, broadcast, leader, metrics,
+    sync::{self, Error, Filter, RemoteConfig},
+};
+
+const EOF_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Newline delimited json that may still be written to.
+/// A line without a trailing newline is kept until the rest of it arrives.
+struct Ndjson {
+    reader: BufReader<tokio::fs::File>,
+    buf: String,
+}
+
+impl Ndjson {
+    async fn open(path: &Path) -> Result<Ndjson, Error> {
+        let file = tokio::fs::File::open(path)
+            .await
+            .wrap_err_with(|| format!("opening {}", path.display()))?;
+        Ok(Ndjson {
+            reader: BufReader::new(file),
+            buf: String::new(),
+        })
+    }
+
+    /// Returns None at the end of the file
+    async fn next<T: DeserializeOwned>(&mut self) -> Result<Option<T>, Error> {
+        self.next_with(|line| serde_json::from_str(line)).await
+    }
+
+    async fn next_with<T>(
+        &mut self,
+        decode: impl Fn(&str) -> serde_json::Result<T>,
+    ) -> Result<Option<T>, Error> {
+        loop {
+            let n = self
+                .reader
+                .read_line(&mut self.buf)
+                .await
+                .wrap_err("reading line")?;
+            if self.buf.ends_with('\n') {
+                let line = std::mem::take(&mut self.buf);
+                if line.trim().is_empty() {
+                    continue;
+                }
+                return Ok(Some(decode(&line).wrap_err("decoding line")?));
+            }
+            if n == 0 {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Ingests a chain from files instead of an rpc. Chains whose url is
+/// file:///some/dir read blocks.ndjson and logs.ndjson from the dir.
+/// Each line is a block (with its transactions) or a log in the rpc's json shape.
+/// Both files are ordered by block number. Blocks at or below the
+/// local latest block are skipped so files can be replayed and appended to.
+pub struct Ingester {
+    pub chain: api::Chain,
+    pub batch_size: u16,
+    pub filter: Filter,
+    pub partition_blocks: u64,
+
+    be_pool: Pool,
+    broadcaster: Arc<broadcast::Channel>,
+    lock: Arc<leader::Lock>,
+    blocks: Ndjson,
+    logs: Ndjson,
+    // the adapter's fields in each block's transactions
+    extra: jrpc::ExtraFields,
+    // the first log of the next batch
+    next_log: Option<jrpc::Log>,
+    partition_max_block: Option<u64>,
+}
+
+impl Ingester {
+    pub async fn new(
+        config: RemoteConfig,
+        be_pool: Pool,
+        broadcaster: Arc<broadcast::Channel>,
+        lock: Arc<leader::Lock>,
+    ) -> Result<Ingester, Error> {
+        let dir = config
+            .url
+            .to_file_path()
+            .map_err(|_| eyre!("invalid file url {}", config.url))?;
+        Ok(Ingester {
+            extra: config.filter.adapter.extra_fields(),
+            chain: config.chain.into(),
+            batch_size: config.batch_size.max(1),
+            filter: config.filter,
+            partition_blocks: config.partition_blocks,
+            be_pool,
+            broadcaster,
+            lock,
+            blocks: Ndjson::open(&dir.join("blocks.ndjson")).await?,
+            logs: Ndjson::open(&dir.join("logs.ndjson")).await?,
+            next_log: None,
+            partition_max_block: None,
+        })
+    }
+
+    /// Returns when the files contain something that can't be ingested.
+    /// The sync loop will start a new ingester from the local latest block.
+    #[tracing::instrument(skip_all fields(event, chain = self.chain.0))]
+    pub async fn run(mut self) {
+        if let Err(e) = sync::record_filter(&self.be_pool, self.chain, &self.filter).await {
+            tracing::error!("recording filter {:?}", e);
+            return;
+        }
+        loop {
+            match self.ingest().await {
+                Ok(0) => tokio::time::sleep(EOF_INTERVAL).await,
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::error!("ingesting files: {:?}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Copies the next batch_size blocks from the files.
+    /// Returns the number of blocks copied.
+    #[tracing::instrument(level="info" skip_all, fields(from, to, blocks, txs, logs))]
+    async fn ingest(&mut self) -> Result<u64, Error> {
+        let local = self.local_latest().await?;
+        let mut blocks: Vec<jrpc::Block> = vec![];
+        while blocks.len() < self.batch_size as usize {
+            match self.blocks.next_with(|line| self.extra.block(line)).await? {
+                Some(b) if local.is_some_and(|(n, _)| b.number.to::<u64>() <= n) => continue,
+                Some(b) => blocks.push(b),
+                None => break,
+            }
+        }
+        let (from, to) = match (blocks.first(), blocks.last()) {
+            (Some(first), Some(last)) => (first.number.to::<u64>(), last.number.to::<u64>()),
+            _ => return Ok(0),
+        };
+        tracing::Span::current()
+            .record("from", from)
+            .record("to", to);
+        if let Some((n, hash)) = local {
+            if from != n + 1 || blocks[0].parent_hash != hash {
+                return Err(Error::Fatal(eyre!(
+                    "block {} doesn't follow local latest block {} {}",
+                    from,
+                    n,
+                    hash
+                )));
+            }
+        }
+        let mut logs = self.logs_through(to).await?;
+        logs.retain(|l| l.block_number.to::<u64>() >= from && self.filter.log_filter.matches(l));
+        if !self.filter.logs {
+            logs.clear();
+        }
+
+        sync::add_timestamp(&mut blocks, &mut logs);
+        sync::validate_blocks(from, to, &blocks)?;
+        if self.filter.logs && self.filter.log_filter.is_empty() {
+            sync::validate_logs(&blocks, &logs)?;
+        }
+        let mut pg = self.be_pool.get().await.wrap_err("pg pool")?;
+        let pgtx = pg.transaction().await?;
+        if self.partition_max_block.is_none_or(|max| to > max) {
+            let partitions =
+                sync::setup_tables(&pgtx, self.chain.0, self.partition_blocks, from, to).await?;
+            self.partition_max_block = partitions.last().map(|p| p.to - 1);
+        }
+        let (num_blocks, num_txs, num_logs) = sync::copy(
+            &pgtx,
+            self.chain,
+            &self.filter,
+            &blocks,
+            logs,
+            sync::CopyMode::Merge,
+        )
+        .await?;
+        broadcast::notify(&pgtx, self.chain.0, to).await?;
+        self.lock.check().await?;
+        pgtx.commit().await.wrap_err("unable to commit tx")?;
+        metrics::ingested(self.chain, num_blocks, num_txs, num_logs);
+        metrics::heads(self.chain, to, None);
+        self.broadcaster.update(self.chain.0);
+        let _ = self.broadcaster.json_updates.send(serde_json::json!({
+            "new_block": "local",
+            "chain": self.chain.0,
+            "num": to,
+        }));
+        tracing::Span::current()
+            .record("blocks", num_blocks)
+            .record("logs", num_logs)
+            .record("txs", num_txs);
+        Ok(num_blocks)
+    }
+
+    /// Reads logs until one is past block n
+    async fn logs_through(&mut self, n: u64) -> Result<Vec<jrpc::Log>, Error> {
+        let mut logs = vec![];
+        loop {
+            let log = match self.next_log.take() {
+                Some(log) => log,
+                None => match self.logs.next::<jrpc::Log>().await? {
+                    Some(log) => log,
+                    None => return Ok(logs),
+                },
+            };
+            if log.block_number.to::<u64>() > n {
+                self.next_log = Some(log);
+                return Ok(logs);
+            }
+            logs.push(log);
+        }
+    }
+
+    async fn local_latest(&self) -> Result<Option<(u64, BlockHash)>, Error> {
+        let pg = self.be_pool.get().await.wrap_err("pg pool")?;
+        Ok(pg
+            .query_opt(
+                "select num, hash from blocks where chain = $1 order by num desc limit 1",
+                &[&self.chain],
+            )
+            .await?
+            .map(|row| (row.get::<&str, U64>("num").to(), row.get("hash"))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::Ndjson;
+
+    #[tokio::test]
+    async fn test_ndjson_partial_line() {
+        let path = std::env::temp_dir().join(format!("ndjson-{}", std::process::id()));
+        let mut file = std::fs::File::create(&path).unwrap();
+        write!(file, "{{\"a\": 1}}\n{{\"a\":").unwrap();
+        let mut ndjson = Ndjson::open(&path).await.unwrap();
+        let v: serde_json::Value = ndjson.next().await.unwrap().unwrap();
+        assert_eq!(v["a"], 1);
+        assert!(ndjson.next::<serde_json::Value>().await.unwrap().is_none());
+        writeln!(file, " 2}}").unwrap();
+        let v: serde_json::Value = ndjson.next().await.unwrap().unwrap();
+        assert_eq!(v["a"], 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,536 @@
+use std::path::{Path, PathBuf};
+
+use alloy::primitives::U64;
+use deadpool_postgres::Pool;
+use eyre::{eyre, Context};
+use futures::{pin_mut, StreamExt};
+use tokio::io::AsyncWriteExt;
+use tokio_postgres::Transaction;
+
+use crate::sync::Error;
+
+pub const DEFAULT_BLOCKS: u64 = 2000000;
+
+/// Tables that are partitioned by chain and then by block range.
+/// They share partition bounds so that a block range
+/// can be detached from all of them at once.
+pub const TABLES: [&str; 5] = ["blocks", "txs", "logs", "traces", "withdrawals"];
+
+/// The unique indexes on the natural keys of TABLES as (table, name, columns).
+/// schema.sql creates them `on only` the parent tables so that new
+/// partitions get them. `repair unique-indexes` adds them to a chain's
+/// partitions that were created before.
+pub const UNIQUE_INDEXES: [(&str, &str, &str); 5] = [
+    ("blocks", "blocks_chain_num", "chain, num"),
+    ("txs", "txs_chain_block_idx", "chain, block_num, idx"),
+    (
+        "logs",
+        "logs_chain_block_log_idx",
+        "chain, block_num, log_idx",
+    ),
+    (
+        "traces",
+        "traces_chain_block_trace_idx",
+        "chain, block_num, tx_idx, trace_idx",
+    ),
+    (
+        "withdrawals",
+        "withdrawals_chain_block_idx",
+        "chain, block_num, idx",
+    ),
+];
+
+/// Errors unless each of the chain's tables has its unique index on every
+/// partition. Sync relies on them to keep copies of a block range from
+/// duplicating rows.
+pub async fn check_unique_indexes(pgtx: &Transaction<'_>, chain: u64) -> Result<(), Error> {
+    for (table, name, _) in UNIQUE_INDEXES {
+        let chain_table = format!("{table}_c{chain}");
+        let valid = pgtx
+            .query_opt(
+                "
+                select x.indisvalid as valid
+                from pg_inherits i
+                join pg_index x on x.indexrelid = i.inhrelid
+                where i.inhparent = to_regclass($1) and x.indrelid = to_regclass($2)
+                ",
+                &[&name, &chain_table],
+            )
+            .await?
+            .is_some_and(|row| row.get("valid"));
+        if !valid {
+            return Err(Error::Fatal(eyre!(
+                "{chain_table} is missing unique index {name}. run repair duplicates and then repair unique-indexes"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// A block range partition of one chain's table. `to` is exclusive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Partition {
+    pub name: String,
+    pub from: u64,
+    pub to: u64,
+}
+
+impl Partition {
+    pub fn contains(&self, n: u64) -> bool {
+        self.from <= n && n < self.to
+    }
+}
+
+/// Partitions are named after their first block in millions.
+/// Spans that don't start on a million include the remainder
+/// so that smaller spans don't collide.
+pub fn label(from: u64) -> String {
+    match from % 1000000 {
+        0 => format!("{}", from / 1000000),
+        r => format!("{}_{}", from / 1000000, r),
+    }
+}
+
+/// Lists the range partitions of a chain's table (eg blocks_c1) ordered by their first block.
+/// Returns an empty list when the table doesn't exist yet.
+pub async fn list(pgtx: &Transaction<'_>, table: &str) -> Result<Vec<Partition>, Error> {
+    let mut partitions = pgtx
+        .query(
+            "
+            select c.relname::text as name, pg_get_expr(c.relpartbound, c.oid) as bound
+            from pg_inherits i
+            join pg_class c on c.oid = i.inhrelid
+            where i.inhparent = to_regclass($1)
+            ",
+            &[&table],
+        )
+        .await?
+        .iter()
+        .map(|row| {
+            let bound: String = row.get("bound");
+            match parse_bound(&bound) {
+                Some((from, to)) => Ok(Partition {
+                    name: row.get("name"),
+                    from,
+                    to,
+                }),
+                None => Err(Error::Fatal(eyre!("unexpected partition bound {}", bound))),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    partitions.sort_by_key(|p| p.from);
+    Ok(partitions)
+}
+
+// FOR VALUES FROM ('0') TO ('2000000')
+fn parse_bound(bound: &str) -> Option<(u64, u64)> {
+    let mut nums = bound
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|s| !s.is_empty())
+        .map(str::parse);
+    match (nums.next(), nums.next(), nums.next()) {
+        (Some(Ok(from)), Some(Ok(to)), None) => Some((from, to)),
+        _ => None,
+    }
+}
+
+/// The partitions needed to cover from..=to given the existing partitions.
+/// New partitions are aligned to the span but are shortened to fit
+/// between existing partitions, which may have been created with another span.
+pub fn missing(existing: &[Partition], span: u64, from: u64, to: u64) -> Vec<(u64, u64)> {
+    let span = span.max(1);
+    let mut res = vec![];
+    let mut n = from;
+    while n <= to {
+        if let Some(p) = existing.iter().find(|p| p.contains(n)) {
+            n = p.to;
+            continue;
+        }
+        let start = existing
+            .iter()
+            .map(|p| p.to)
+            .filter(|&end| end <= n)
+            .fold((n / span) * span, u64::max);
+        let end = existing
+            .iter()
+            .map(|p| p.from)
+            .filter(|&start| start > n)
+            .fold((n / span + 1) * span, u64::min);
+        res.push((start, end));
+        n = end;
+    }
+    res
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Action {
+    /// Detached partitions are left in the database as regular tables
+    /// named with the DETACHED suffix
+    Detach,
+    Drop,
+    /// Detached partitions are exported as csv to the archive dir and then dropped
+    Archive,
+}
+
+impl Action {
+    pub fn parse(s: &str) -> Option<Action> {
+        match s {
+            "detach" => Some(Action::Detach),
+            "drop" => Some(Action::Drop),
+            "archive" => Some(Action::Archive),
+            _ => None,
+        }
+    }
+}
+
+/// Added to the names of detached partitions so that setup_tables
+/// creates an empty partition if the range is synced again
+pub const DETACHED: &str = "_detached";
+
+/// Removes partitions whose blocks are all older than
+/// `blocks` behind the latest block or older than `days`.
+/// The partition holding the latest block is never removed.
+/// Partitions are detached concurrently so that queries and sync
+/// aren't blocked. A detach interrupted by a failure is finished on
+/// the next apply and with drop or archive, partitions that are still
+/// detached (eg after a failed archive) are removed.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Retention {
+    pub blocks: Option<u64>,
+    pub days: Option<u32>,
+    pub action: Action,
+}
+
+impl Retention {
+    /// Returns the names of the removed blocks partitions
+    pub async fn apply(
+        &self,
+        pool: &Pool,
+        chain: u64,
+        archive_dir: Option<&Path>,
+    ) -> Result<Vec<String>, Error> {
+        if self.action == Action::Archive && archive_dir.is_none() {
+            return Err(Error::Fatal(eyre!(
+                "archive retention requires an archive dir"
+            )));
+        }
+        let mut pg = pool.get().await.wrap_err("pg pool")?;
+        for (parent, name) in pending(&pg, chain).await? {
+            tracing::info!("retention chain={} finalizing {}", chain, name);
+            pg.batch_execute(&format!(
+                "alter table {parent} detach partition {name} finalize"
+            ))
+            .await?;
+        }
+        for name in detached(&pg, chain).await? {
+            let name = match name.strip_suffix(DETACHED) {
+                Some(name) => name.to_string(),
+                None => {
+                    rename(&pg, &name).await?;
+                    name
+                }
+            };
+            if self.action != Action::Detach {
+                tracing::info!("retention chain={} retrying {}", chain, name);
+                self.finish(&pg, &name, archive_dir).await?;
+            }
+        }
+        let pgtx = pg.transaction().await?;
+        let partitions = list(&pgtx, &format!("blocks_c{chain}")).await?;
+        let latest = pgtx
+            .query_one(
+                "select max(num) as num from blocks where chain = $1",
+                &[&U64::from(chain)],
+            )
+            .await?
+            .get::<&str, Option<i64>>("num");
+        pgtx.commit().await?;
+        let latest = match latest {
+            Some(n) => n as u64,
+            None => return Ok(vec![]),
+        };
+        let mut removed = vec![];
+        for p in partitions {
+            if p.to > latest || !self.expired(pool, chain, &p, latest).await? {
+                break;
+            }
+            self.remove(pool, chain, &label(p.from), archive_dir)
+                .await?;
+            tracing::info!(
+                "retention chain={} partition={} action={:?}",
+                chain,
+                p.name,
+                self.action
+            );
+            removed.push(p.name);
+        }
+        Ok(removed)
+    }
+
+    async fn expired(
+        &self,
+        pool: &Pool,
+        chain: u64,
+        p: &Partition,
+        latest: u64,
+    ) -> Result<bool, Error> {
+        if self
+            .blocks
+            .is_some_and(|n| p.to.saturating_add(n) <= latest)
+        {
+            return Ok(true);
+        }
+        let days = match self.days {
+            Some(days) => days as i32,
+            None => return Ok(false),
+        };
+        let row = pool
+            .get()
+            .await
+            .wrap_err("pg pool")?
+            .query_opt(
+                "
+                select timestamp < now() - make_interval(days => $4) as expired
+                from blocks
+                where chain = $1 and num >= $2 and num < $3
+                order by num desc
+                limit 1
+                ",
+                &[
+                    &U64::from(chain),
+                    &U64::from(p.from),
+                    &U64::from(p.to),
+                    &days,
+                ],
+            )
+            .await?;
+        Ok(row.is_none_or(|row| row.get("expired")))
+    }
+
+    async fn remove(
+        &self,
+        pool: &Pool,
+        chain: u64,
+        label: &str,
+        archive_dir: Option<&Path>,
+    ) -> Result<(), Error> {
+        let pg = pool.get().await.wrap_err("pg pool")?;
+        let mut detached = vec![];
+        for table in TABLES {
+            let name = format!("{table}_c{chain}_b{label}");
+            let exists = pg
+                .query_one("select to_regclass($1) is not null as exists", &[&name])
+                .await?
+                .get::<&str, bool>("exists");
+            if exists {
+                // concurrently can't run inside a transaction
+                pg.batch_execute(&format!(
+                    "alter table {table}_c{chain} detach partition {name} concurrently"
+                ))
+                .await?;
+                rename(&pg, &name).await?;
+                detached.push(name);
+            }
+        }
+        if self.action == Action::Detach {
+            return Ok(());
+        }
+        for name in detached {
+            self.finish(&pg, &name, archive_dir).await?;
+        }
+        Ok(())
+    }
+
+    /// Archives and drops a detached partition. A partition left
+    /// behind by a failure is found by `detached` on the next apply.
+    async fn finish(
+        &self,
+        pg: &tokio_postgres::Client,
+        name: &str,
+        archive_dir: Option<&Path>,
+    ) -> Result<(), Error> {
+        let table = format!("{name}{DETACHED}");
+        if let (Action::Archive, Some(dir)) = (&self.action, archive_dir) {
+            archive(pg, dir, &table, name).await?;
+        }
+        pg.batch_execute(&format!("drop table {table}")).await?;
+        Ok(())
+    }
+}
+
+async fn rename(pg: &tokio_postgres::Client, name: &str) -> Result<(), Error> {
+    pg.batch_execute(&format!("alter table {name} rename to {name}{DETACHED}"))
+        .await?;
+    Ok(())
+}
+
+/// The chain's range partitions, with their parents, whose concurrent
+/// detach was interrupted
+async fn pending(pg: &tokio_postgres::Client, chain: u64) -> Result<Vec<(String, String)>, Error> {
+    let pattern = format!("^({})_c{chain}_b[0-9_]+$", TABLES.join("|"));
+    Ok(pg
+        .query(
+            "
+            select p.relname::text as parent, c.relname::text as name
+            from pg_inherits i
+            join pg_class c on c.oid = i.inhrelid
+            join pg_class p on p.oid = i.inhparent
+            where i.inhdetachpending
+            and c.relnamespace = current_schema()::regnamespace
+            and c.relname ~ $1
+            order by c.relname
+            ",
+            &[&pattern],
+        )
+        .await?
+        .iter()
+        .map(|row| (row.get("parent"), row.get("name")))
+        .collect())
+}
+
+/// The chain's range partitions that were detached but not dropped,
+/// including ones that a failure left without the DETACHED suffix
+async fn detached(pg: &tokio_postgres::Client, chain: u64) -> Result<Vec<String>, Error> {
+    let pattern = format!("^({})_c{chain}_b[0-9_]+({DETACHED})?$", TABLES.join("|"));
+    Ok(pg
+        .query(
+            "
+            select c.relname::text as name
+            from pg_class c
+            where c.relkind = 'r'
+            and c.relnamespace = current_schema()::regnamespace
+            and c.relname ~ $1
+            and not exists (select 1 from pg_inherits i where i.inhrelid = c.oid)
+            order by c.relname
+            ",
+            &[&pattern],
+        )
+        .await?
+        .iter()
+        .map(|row| row.get("name"))
+        .collect())
+}
+
+/// Writes the table to `dir/name.csv` and returns the file's path
+async fn archive(
+    pg: &tokio_postgres::Client,
+    dir: &Path,
+    table: &str,
+    name: &str,
+) -> Result<PathBuf, Error> {
+    tokio::fs::create_dir_all(dir)
+        .await
+        .wrap_err("creating archive dir")?;
+    let path = dir.join(format!("{name}.csv"));
+    let mut file = tokio::fs::File::create(&path)
+        .await
+        .wrap_err_with(|| format!("creating {}", path.display()))?;
+    let stream = pg
+        .copy_out(&format!("copy {table} to stdout with (format csv, header)"))
+        .await?;
+    pin_mut!(stream);
+    while let Some(chunk) = stream.next().await {
+        file.write_all(&chunk?).await.wrap_err("writing archive")?;
+    }
+    file.sync_all().await.wrap_err("syncing archive")?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{label, missing, parse_bound, Action, Partition, Retention};
+    use crate::sync;
+
+    static SCHEMA: &str = include_str!("./sql/schema.sql");
+
+    fn partition(from: u64, to: u64) -> Partition {
+        Partition {
+            name: format!("blocks_c1_b{}", label(from)),
+            from,
+            to,
+        }
+    }
+
+    #[test]
+    fn test_label() {
+        assert_eq!(label(0), "0");
+        assert_eq!(label(4000000), "4");
+        assert_eq!(label(4500000), "4_500000");
+    }
+
+    #[test]
+    fn test_parse_bound() {
+        assert_eq!(
+            parse_bound("FOR VALUES FROM ('0') TO ('2000000')"),
+            Some((0, 2000000))
+        );
+        assert_eq!(parse_bound("FOR VALUES IN ('1')"), None);
+    }
+
+    #[test]
+    fn test_missing() {
+        assert_eq!(
+            missing(&[], 1000, 1500, 3200),
+            vec![(1000, 2000), (2000, 3000), (3000, 4000)]
+        );
+        let existing = [partition(0, 2000000)];
+        assert_eq!(missing(&existing, 2000000, 10, 1999999), vec![]);
+        // the span changed from 2M to 500k
+        assert_eq!(
+            missing(&existing, 500000, 1999999, 2000001),
+            vec![(2000000, 2500000)]
+        );
+        // a larger span is shortened to fit before the existing partition
+        let existing = [partition(2000000, 4000000)];
+        assert_eq!(
+            missing(&existing, 3000000, 1500000, 1500000),
+            vec![(0, 2000000)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retry_detached() {
+        let pool = shared::pg::test::new(SCHEMA).await;
+        let mut pg = pool.get().await.unwrap();
+        let pgtx = pg.transaction().await.unwrap();
+        sync::setup_tables(&pgtx, 1, 1000, 0, 1999).await.unwrap();
+        pgtx.commit().await.unwrap();
+        // a failed drop after detaching
+        pg.batch_execute("alter table logs_c1 detach partition logs_c1_b0")
+            .await
+            .unwrap();
+        let detach = Retention {
+            blocks: Some(1),
+            days: None,
+            action: Action::Detach,
+        };
+        detach.apply(&pool, 1, None).await.unwrap();
+        let exists = |name: &'static str| {
+            let pool = pool.clone();
+            async move {
+                pool.get()
+                    .await
+                    .unwrap()
+                    .query_one("select to_regclass($1) is not null", &[&name])
+                    .await
+                    .unwrap()
+                    .get::<usize, bool>(0)
+            }
+        };
+        assert!(!exists("logs_c1_b0").await);
+        assert!(exists("logs_c1_b0_detached").await);
+        // the range can be synced again
+        let pgtx = pg.transaction().await.unwrap();
+        sync::setup_tables(&pgtx, 1, 1000, 0, 1999).await.unwrap();
+        pgtx.commit().await.unwrap();
+        assert!(exists("logs_c1_b0").await);
+        let drop = Retention {
+            action: Action::Drop,
+            ..detach
+        };
+        drop.apply(&pool, 1, None).await.unwrap();
+        assert!(!exists("logs_c1_b0_detached").await);
+        assert!(exists("logs_c1_b0").await);
+        assert!(exists("logs_c1_b0_1000").await);
+    }
+}
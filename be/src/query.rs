@@ -16,7 +16,7 @@ use std::{
 
 use crate::{
     abi::{self},
-    api, cursor,
+    adapter, api, cursor,
 };
 
 macro_rules! no {
@@ -106,15 +106,34 @@ impl Relation {
     }
 
     fn has_field(&self, id: &Ident) -> bool {
-        //TODO find a better way of dealing with this, perhaps we should keep
-        //a mapping of base table -> columns
-        if self.named(&Ident::new("blocks")) && id.value.to_lowercase() == "block_num" {
-            return false;
-        }
-        base_column_type(id).is_some()
+        self.column_type(id).is_some()
             || self.abi_schema.as_ref().map(|e| e.get_field(id)).is_some()
     }
 
+    // tokens isn't keyed by block so its columns are kept
+    // apart from the other base tables' columns
+    fn is_tokens(&self) -> bool {
+        self.abi_schema.is_none() && self.table_name.value.to_lowercase() == "tokens"
+    }
+
+    // the table that the relation's CTE reads from
+    fn base_table(&self) -> String {
+        match self.abi_schema.as_ref() {
+            Some(abi_schema) => abi_schema.base_table(),
+            None => self.table_name.value.to_lowercase(),
+        }
+    }
+
+    fn column_type(&self, id: &Ident) -> Option<ast::DataType> {
+        if self.is_tokens() {
+            token_column_type(id)
+        } else if table_has_column(&self.base_table(), &id.value.to_lowercase()) {
+            base_column_type(id)
+        } else {
+            None
+        }
+    }
+
     fn to_sql(&self, cursor: cursor::Cursor) -> String {
         let mut res: Vec<String> = Vec::new();
         res.push(format!("{} as not materialized (", self.table_name));
@@ -132,7 +151,7 @@ impl Relation {
 
         for col in self.selected_fields.iter().sorted() {
             let in_statements = statements.contains_key(&col.value.to_lowercase());
-            if !in_statements && base_column_type(col).is_some() {
+            if !in_statements && self.column_type(col).is_some() {
                 select_list.push(col.to_string())
             }
         }
@@ -144,7 +163,9 @@ impl Relation {
         res.push(select_list.join(","));
 
         let mut predicates = vec![];
-        if self.table_name.value.to_lowercase() == "blocks" {
+        if self.is_tokens() {
+            predicates.push(cursor.chains_sql());
+        } else if self.table_name.value.to_lowercase() == "blocks" {
             predicates.push(cursor.to_sql("num"));
         } else {
             predicates.push(cursor.to_sql("block_num"));
@@ -474,7 +495,9 @@ impl UserQuery {
                 };
                 Ok(())
             } else if let Some(data_type) = left.last().and_then(|id| base_column_type(&id)) {
-                *right = bytes_to_expr(lit, data_type)?;
+                if data_type != ast::DataType::Text {
+                    *right = bytes_to_expr(lit, data_type)?;
+                }
                 Ok(())
             } else {
                 Ok(())
@@ -788,7 +811,7 @@ fn expr_to_bytes(expr: &ast::Expr) -> Option<Vec<u8>> {
 
 fn bytes_to_expr(bytes: Vec<u8>, to: ast::DataType) -> Result<ast::Expr, api::Error> {
     match to {
-        ast::DataType::Int64 if bytes.len() <= 32 => {
+        ast::DataType::Int64 | ast::DataType::Numeric(_) if bytes.len() <= 32 => {
             let n = U256::from_be_slice(&bytes);
             Ok(ast::Expr::Value(ast::Value::Number(n.to_string(), false)))
         }
@@ -824,6 +847,10 @@ fn base_column_type(id: &Ident) -> Option<ast::DataType> {
         // Blocks
         "num" | "size" => Some(ast::DataType::Int64),
         "receipts_root" | "state_root" | "extra_data" | "miner" => Some(ast::DataType::Bytea),
+        "parent_hash" | "withdrawals_root" => Some(ast::DataType::Bytea),
+        "base_fee_per_gas" | "blob_gas_used" | "excess_blob_gas" => {
+            Some(ast::DataType::Numeric(ast::ExactNumberInfo::None))
+        }
 
         "timestamp" => Some(ast::DataType::Timestamp(None, ast::TimezoneInfo::Tz)),
         "gas_limit" | "gas_used" => Some(ast::DataType::Numeric(ast::ExactNumberInfo::None)),
@@ -834,6 +861,20 @@ fn base_column_type(id: &Ident) -> Option<ast::DataType> {
         "value" => Some(ast::DataType::Numeric(ast::ExactNumberInfo::None)),
         "gas" | "gas_price" => Some(ast::DataType::Numeric(ast::ExactNumberInfo::None)),
         "calls" => Some(ast::DataType::JSONB),
+        "status" => Some(ast::DataType::Int64),
+        "cumulative_gas_used" | "effective_gas_price" => {
+            Some(ast::DataType::Numeric(ast::ExactNumberInfo::None))
+        }
+        "contract_address" | "logs_bloom" => Some(ast::DataType::Bytea),
+
+        // Traces
+        "tx_idx" | "trace_idx" | "depth" => Some(ast::DataType::Int64),
+        "call_type" | "error" => Some(ast::DataType::Text),
+        "output" => Some(ast::DataType::Bytea),
+
+        // Withdrawals
+        "validator_index" => Some(ast::DataType::Int64),
+        "amount" => Some(ast::DataType::Numeric(ast::ExactNumberInfo::None)),
 
         // Logs
         "tx_hash" | "address" | "topics" | "data" => Some(ast::DataType::Bytea),
@@ -844,6 +885,110 @@ fn base_column_type(id: &Ident) -> Option<ast::DataType> {
         "nonce" | "hash" => Some(ast::DataType::Bytea),
         "block_num" => Some(ast::DataType::Int64),
         "block_timestamp" => Some(ast::DataType::Timestamp(None, ast::TimezoneInfo::Tz)),
+
+        // Chain specific txs columns
+        name => adapter::column_type(name),
+    }
+}
+
+/// base_column_type is keyed by name alone so this keeps
+/// a table from claiming another table's columns.
+/// Tables that aren't base tables keep every name.
+fn table_has_column(table: &str, name: &str) -> bool {
+    let columns: &[&str] = match table {
+        "blocks" => &[
+            "chain",
+            "num",
+            "timestamp",
+            "size",
+            "gas_limit",
+            "gas_used",
+            "nonce",
+            "hash",
+            "receipts_root",
+            "state_root",
+            "extra_data",
+            "miner",
+            "parent_hash",
+            "logs_bloom",
+            "base_fee_per_gas",
+            "blob_gas_used",
+            "excess_blob_gas",
+            "withdrawals_root",
+        ],
+        "txs" if adapter::column_type(name).is_some() => return true,
+        "txs" => &[
+            "chain",
+            "block_num",
+            "block_timestamp",
+            "idx",
+            "type",
+            "gas",
+            "gas_price",
+            "nonce",
+            "hash",
+            "from",
+            "to",
+            "input",
+            "value",
+            "fee_token",
+            "calls",
+            "status",
+            "gas_used",
+            "cumulative_gas_used",
+            "effective_gas_price",
+            "contract_address",
+            "logs_bloom",
+        ],
+        "logs" => &[
+            "chain",
+            "block_num",
+            "block_timestamp",
+            "log_idx",
+            "tx_hash",
+            "address",
+            "topics",
+            "data",
+        ],
+        "traces" => &[
+            "chain",
+            "block_num",
+            "block_timestamp",
+            "tx_idx",
+            "tx_hash",
+            "trace_idx",
+            "depth",
+            "call_type",
+            "from",
+            "to",
+            "value",
+            "input",
+            "output",
+            "gas",
+            "gas_used",
+            "error",
+        ],
+        "withdrawals" => &[
+            "chain",
+            "block_num",
+            "block_timestamp",
+            "idx",
+            "validator_index",
+            "address",
+            "amount",
+        ],
+        _ => return true,
+    };
+    columns.contains(&name) || matches!(name, "abi_address" | "abi_uint" | "abi_int")
+}
+
+fn token_column_type(id: &Ident) -> Option<ast::DataType> {
+    match id.value.to_lowercase().as_str() {
+        "chain" | "decimals" => Some(ast::DataType::Int64),
+        "address" => Some(ast::DataType::Bytea),
+        "name" | "symbol" => Some(ast::DataType::Text),
+        "total_supply" => Some(ast::DataType::Numeric(ast::ExactNumberInfo::None)),
+        "updated_at" => Some(ast::DataType::Timestamp(None, ast::TimezoneInfo::Tz)),
         _ => None,
     }
 }
@@ -950,6 +1095,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_cursor_limit() {
+        let mut cursor = cursor::Cursor::default();
+        cursor.set_block_height(10, 42);
+        cursor.set_block_limit(10, 50);
+        cursor.set_block_limit(8453, 100);
+        let _ = sql(
+            &mut cursor,
+            vec![],
+            "select hash from txs where chain in (8453, 10)",
+        )
+        .unwrap();
+        assert_eq!(
+            cursor.to_sql("foo"),
+            "((chain = 10 and foo >= 42 and foo <= 50) or (chain = 8453 and foo <= 100))"
+        );
+    }
+
     #[tokio::test]
     async fn test_sub_query() {
         check_sql(
@@ -981,6 +1144,52 @@ mod tests {
         .await;
     }
 
+    #[tokio::test]
+    async fn test_tokens_table() {
+        check_sql(
+            vec![],
+            r#"select symbol, decimals from tokens where address = 0xab"#,
+            r#"
+                with tokens as not materialized (
+                    select address, decimals, symbol
+                    from tokens
+                    where chain = 1
+                )
+                select symbol, decimals from tokens where address = '\xab'
+            "#,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_tokens_join() {
+        check_sql(
+            vec!["Transfer(address indexed from, address indexed to, uint256 value)"],
+            r#"
+            select t.symbol, t.decimals, value
+            from transfer
+            join tokens t on t.address = transfer.address
+            "#,
+            r#"
+            with tokens as not materialized (
+                select address, decimals, symbol
+                from tokens
+                where chain = 1
+            ),
+            transfer as not materialized (
+                select address, abi_fixed_bytes(data, 0, 32) as value
+                from logs
+                where chain = 1
+                and topics [1] = '\xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef'
+            )
+            select t.symbol, t.decimals, abi_uint(value) as value
+            from transfer
+            join tokens as t on t.address = transfer.address
+            "#,
+        )
+        .await;
+    }
+
     #[tokio::test]
     async fn test_logs_table() {
         check_sql(
@@ -1019,6 +1228,119 @@ mod tests {
         .await;
     }
 
+    #[tokio::test]
+    async fn test_txs_receipts() {
+        check_sql(
+            vec![],
+            r#"select hash, gas_used, contract_address from txs where status = 0"#,
+            r#"
+                with txs as not materialized (
+                    select contract_address, gas_used, hash, status
+                    from txs
+                    where chain = 1
+                )
+                select hash, gas_used, contract_address
+                from txs
+                where status = 0
+            "#,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_traces() {
+        check_sql(
+            vec!["trace transfer(address to, uint amount)"],
+            r#"select "from", amount from transfer where call_type = 'CALL' and depth > 0"#,
+            r#"
+                with transfer as not materialized (
+                    select
+                        call_type,
+                        depth,
+                        "from",
+                        abi_fixed_bytes(substring(input, 5), 32, 32) as amount
+                    from traces
+                    where chain = 1
+                    and (substring(input, 1, 4) = '\xa9059cbb' and input is not null and octet_length(input) >= 64)
+                )
+                select "from", abi_uint(amount) as amount
+                from transfer
+                where call_type = 'CALL'
+                and depth > 0
+            "#,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_withdrawals() {
+        check_sql(
+            vec![],
+            r#"
+                select b.num, b.base_fee_per_gas, w.amount
+                from blocks b
+                join withdrawals w on w.block_num = b.num
+                where w.amount > 32000000000
+                and b.parent_hash = 0xaa
+            "#,
+            r#"
+                with
+                blocks as not materialized (
+                    select base_fee_per_gas, num, parent_hash
+                    from blocks
+                    where chain = 1
+                ),
+                withdrawals as not materialized (
+                    select amount, block_num
+                    from withdrawals
+                    where chain = 1
+                )
+                select b.num, b.base_fee_per_gas, w.amount
+                from blocks as b
+                join withdrawals as w on w.block_num = b.num
+                where w.amount > 32000000000
+                and b.parent_hash = '\xaa'
+            "#,
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_table_columns() {
+        check_sql(
+            vec![],
+            r#"
+                select t.hash, status, error, amount
+                from txs t
+                join traces r on r.tx_hash = t.hash
+                join withdrawals w on w.block_num = t.block_num
+            "#,
+            r#"
+                with
+                traces as not materialized (
+                    select error, tx_hash
+                    from traces
+                    where chain = 1
+                ),
+                txs as not materialized (
+                    select block_num, hash, status
+                    from txs
+                    where chain = 1
+                ),
+                withdrawals as not materialized (
+                    select amount, block_num
+                    from withdrawals
+                    where chain = 1
+                )
+                select t.hash, status, error, amount
+                from txs as t
+                join traces as r on r.tx_hash = t.hash
+                join withdrawals as w on w.block_num = t.block_num
+            "#,
+        )
+        .await;
+    }
+
     #[tokio::test]
     async fn test_nested_expressions() {
         check_sql(
@@ -0,0 +1,640 @@
+use std::{collections::VecDeque, fs::File, path::Path, sync::Arc};
+
+use alloy::primitives::{Address, Bytes, FixedBytes, U256, U64};
+use arrow::{
+    array::{
+        Array, ArrayRef, AsArray, BinaryArray, BinaryBuilder, Int64Array, ListBuilder, RecordBatch,
+        StringArray,
+    },
+    datatypes::{DataType, Field, Int64Type, Schema},
+};
+use eyre::{eyre, Context, Result};
+use parquet::{
+    arrow::{arrow_reader::ParquetRecordBatchReaderBuilder, ArrowWriter},
+    basic::Compression,
+    file::{metadata::KeyValue, properties::WriterProperties},
+};
+use shared::jrpc;
+
+use crate::{
+    adapter::{self, Adapter},
+    api, sync,
+};
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Int,
+    Bytes,
+    // numeric columns are stored as decimal strings
+    Numeric,
+    Text,
+    BytesList,
+}
+
+#[derive(Clone, Copy)]
+struct Column {
+    name: &'static str,
+    // sql expression used to select the column
+    expr: &'static str,
+    kind: Kind,
+    nullable: bool,
+}
+
+const fn col(name: &'static str, kind: Kind, nullable: bool) -> Column {
+    Column {
+        name,
+        expr: name,
+        kind,
+        nullable,
+    }
+}
+
+const BLOCKS: &[Column] = &[
+    col("num", Kind::Int, false),
+    Column {
+        name: "timestamp",
+        expr: "extract(epoch from timestamp)",
+        kind: Kind::Int,
+        nullable: false,
+    },
+    col("size", Kind::Int, false),
+    col("gas_limit", Kind::Numeric, false),
+    col("gas_used", Kind::Numeric, false),
+    col("hash", Kind::Bytes, false),
+    col("nonce", Kind::Bytes, false),
+    col("receipts_root", Kind::Bytes, false),
+    col("state_root", Kind::Bytes, false),
+    col("extra_data", Kind::Bytes, false),
+    col("miner", Kind::Bytes, false),
+    col("parent_hash", Kind::Bytes, false),
+    col("logs_bloom", Kind::Bytes, false),
+    col("base_fee_per_gas", Kind::Numeric, true),
+    col("blob_gas_used", Kind::Numeric, true),
+    col("excess_blob_gas", Kind::Numeric, true),
+    col("withdrawals_root", Kind::Bytes, true),
+];
+
+const TXS: &[Column] = &[
+    col("block_num", Kind::Int, false),
+    col("idx", Kind::Int, false),
+    col("type", Kind::Int, false),
+    col("gas", Kind::Numeric, false),
+    col("gas_price", Kind::Numeric, false),
+    col("hash", Kind::Bytes, false),
+    col("nonce", Kind::Bytes, false),
+    Column {
+        name: "from",
+        expr: r#""from""#,
+        kind: Kind::Bytes,
+        nullable: false,
+    },
+    Column {
+        name: "to",
+        expr: r#""to""#,
+        kind: Kind::Bytes,
+        nullable: false,
+    },
+    col("input", Kind::Bytes, false),
+    col("value", Kind::Numeric, false),
+    col("fee_token", Kind::Bytes, true),
+    col("calls", Kind::Text, true),
+    col("status", Kind::Int, true),
+    col("gas_used", Kind::Numeric, true),
+    col("cumulative_gas_used", Kind::Numeric, true),
+    col("effective_gas_price", Kind::Numeric, true),
+    col("contract_address", Kind::Bytes, true),
+    col("logs_bloom", Kind::Bytes, true),
+];
+
+const LOGS: &[Column] = &[
+    col("block_num", Kind::Int, false),
+    col("log_idx", Kind::Int, false),
+    col("tx_hash", Kind::Bytes, false),
+    col("address", Kind::Bytes, false),
+    col("topics", Kind::BytesList, false),
+    col("data", Kind::Bytes, false),
+];
+
+/// TXS followed by the adapter's columns
+fn txs_columns(adapter: &dyn adapter::Adapter) -> Vec<Column> {
+    TXS.iter()
+        .copied()
+        .chain(adapter.columns().iter().map(|c| Column {
+            name: c.name,
+            expr: c.name,
+            kind: match c.kind {
+                adapter::Kind::Bytes => Kind::Bytes,
+                adapter::Kind::Numeric => Kind::Numeric,
+                adapter::Kind::Json => Kind::Text,
+            },
+            nullable: true,
+        }))
+        .collect()
+}
+
+const EXPORT_BLOCKS: u64 = 10000;
+const IMPORT_ROWS: usize = 1000;
+
+fn schema(columns: &[Column]) -> Arc<Schema> {
+    Arc::new(Schema::new(
+        columns
+            .iter()
+            .map(|c| {
+                let data_type = match c.kind {
+                    Kind::Int => DataType::Int64,
+                    Kind::Bytes => DataType::Binary,
+                    Kind::Numeric | Kind::Text => DataType::Utf8,
+                    Kind::BytesList => {
+                        DataType::List(Arc::new(Field::new("item", DataType::Binary, true)))
+                    }
+                };
+                Field::new(c.name, data_type, c.nullable)
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+/// The column's values with a helpful error for unexpected nulls.
+/// The first column of every table is its block number.
+fn values<'a, T: tokio_postgres::types::FromSql<'a>>(
+    table: &str,
+    column: &Column,
+    i: usize,
+    rows: &'a [tokio_postgres::Row],
+) -> Result<Vec<Option<T>>> {
+    rows.iter()
+        .map(|row| match row.try_get::<usize, Option<T>>(i)? {
+            None if !column.nullable => Err(eyre!(
+                "{}.{} is null at block {}. resync it with the repair tool before exporting",
+                table,
+                column.name,
+                row.get::<usize, i64>(0)
+            )),
+            v => Ok(v),
+        })
+        .collect()
+}
+
+fn batch(table: &str, columns: &[Column], rows: &[tokio_postgres::Row]) -> Result<RecordBatch> {
+    let arrays = columns
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            Ok(match c.kind {
+                Kind::Int => {
+                    Arc::new(Int64Array::from(values::<i64>(table, c, i, rows)?)) as ArrayRef
+                }
+                Kind::Bytes => Arc::new(BinaryArray::from(values::<&[u8]>(table, c, i, rows)?)),
+                Kind::Numeric | Kind::Text => {
+                    Arc::new(StringArray::from(values::<String>(table, c, i, rows)?))
+                }
+                Kind::BytesList => {
+                    let mut builder = ListBuilder::new(BinaryBuilder::new());
+                    for v in values::<Vec<&[u8]>>(table, c, i, rows)? {
+                        builder.append_option(v.map(|v| v.into_iter().map(Some)));
+                    }
+                    Arc::new(builder.finish())
+                }
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(RecordBatch::try_new(schema(columns), arrays)?)
+}
+
+/// Writes the chain's blocks, txs and logs for from..=to
+/// to blocks.parquet, txs.parquet and logs.parquet in dir.
+/// txs includes the columns of the chain's adapter.
+/// Returns the number of rows written to each file.
+pub async fn export(
+    pg: &tokio_postgres::Client,
+    chain: u64,
+    adapter: &dyn adapter::Adapter,
+    from: u64,
+    to: u64,
+    dir: &Path,
+) -> Result<[u64; 3]> {
+    let txs = txs_columns(adapter);
+    // (table, block number column, order by, columns)
+    let tables: [(&str, &str, &str, &[Column]); 3] = [
+        ("blocks", "num", "num", BLOCKS),
+        ("txs", "block_num", "block_num, idx", &txs),
+        ("logs", "block_num", "block_num, log_idx", LOGS),
+    ];
+    std::fs::create_dir_all(dir).wrap_err("creating snapshot dir")?;
+    let props = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_key_value_metadata(Some(vec![
+            KeyValue::new("chain".to_string(), chain.to_string()),
+            KeyValue::new("from".to_string(), from.to_string()),
+            KeyValue::new("to".to_string(), to.to_string()),
+            KeyValue::new("adapter".to_string(), adapter.name().to_string()),
+        ]))
+        .build();
+    let mut writers = tables
+        .iter()
+        .map(|(table, _, _, columns)| {
+            let path = dir.join(format!("{table}.parquet"));
+            let file =
+                File::create(&path).wrap_err_with(|| format!("creating {}", path.display()))?;
+            Ok(ArrowWriter::try_new(
+                file,
+                schema(columns),
+                Some(props.clone()),
+            )?)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut counts = [0; 3];
+    for start in (from..=to).step_by(EXPORT_BLOCKS as usize) {
+        let end = to.min(start + EXPORT_BLOCKS - 1);
+        for (i, (table, num, order, columns)) in tables.iter().enumerate() {
+            let exprs = columns
+                .iter()
+                .map(|c| match c.kind {
+                    Kind::Int => format!("({})::int8", c.expr),
+                    Kind::Numeric | Kind::Text => format!("({})::text", c.expr),
+                    Kind::Bytes | Kind::BytesList => c.expr.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            let rows = pg
+                .query(
+                    &format!(
+                        "select {exprs} from {table}
+                        where chain = $1 and {num} >= $2 and {num} <= $3
+                        order by {order}"
+                    ),
+                    &[&U64::from(chain), &U64::from(start), &U64::from(end)],
+                )
+                .await
+                .wrap_err_with(|| format!("querying {table}"))?;
+            if rows.is_empty() {
+                continue;
+            }
+            writers[i].write(&batch(table, columns, &rows)?)?;
+            counts[i] += rows.len() as u64;
+        }
+    }
+    for writer in writers {
+        writer.close()?;
+    }
+    Ok(counts)
+}
+
+type Parse<'a, T> = Box<dyn Fn(&RecordBatch) -> Result<Vec<T>> + 'a>;
+
+/// Reads rows in block order from one parquet file
+/// and hands them out a block range at a time.
+struct Rows<'a, T> {
+    batches: parquet::arrow::arrow_reader::ParquetRecordBatchReader,
+    rows: VecDeque<T>,
+    parse: Parse<'a, T>,
+    num: fn(&T) -> u64,
+}
+
+impl<'a, T> Rows<'a, T> {
+    fn open(
+        dir: &Path,
+        table: &str,
+        parse: impl Fn(&RecordBatch) -> Result<Vec<T>> + 'a,
+        num: fn(&T) -> u64,
+    ) -> Result<(Rows<'a, T>, Vec<KeyValue>)> {
+        let path = dir.join(format!("{table}.parquet"));
+        let file = File::open(&path).wrap_err_with(|| format!("opening {}", path.display()))?;
+        let builder = ParquetRecordBatchReaderBuilder::try_new(file)?.with_batch_size(IMPORT_ROWS);
+        let metadata = builder
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .cloned()
+            .unwrap_or_default();
+        Ok((
+            Rows {
+                batches: builder.build()?,
+                rows: VecDeque::new(),
+                parse: Box::new(parse),
+                num,
+            },
+            metadata,
+        ))
+    }
+
+    fn next_batch(&mut self) -> Result<Option<Vec<T>>> {
+        match self.batches.next() {
+            Some(batch) => Ok(Some((self.parse)(&batch?)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes the rows whose block number is at most n
+    fn take_through(&mut self, n: u64) -> Result<Vec<T>> {
+        let mut res = vec![];
+        loop {
+            while self.rows.front().is_some_and(|r| (self.num)(r) <= n) {
+                res.extend(self.rows.pop_front());
+            }
+            if !self.rows.is_empty() {
+                return Ok(res);
+            }
+            match self.next_batch()? {
+                Some(rows) => self.rows.extend(rows),
+                None => return Ok(res),
+            }
+        }
+    }
+}
+
+fn ints<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a Int64Array> {
+    Ok(batch
+        .column_by_name(name)
+        .ok_or_else(|| eyre!("missing column {name}"))?
+        .as_primitive::<Int64Type>())
+}
+
+fn binary<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a BinaryArray> {
+    batch
+        .column_by_name(name)
+        .ok_or_else(|| eyre!("missing column {name}"))?
+        .as_binary_opt::<i32>()
+        .ok_or_else(|| eyre!("column {name} isn't binary"))
+}
+
+fn strings<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a StringArray> {
+    batch
+        .column_by_name(name)
+        .ok_or_else(|| eyre!("missing column {name}"))?
+        .as_string_opt::<i32>()
+        .ok_or_else(|| eyre!("column {name} isn't a string"))
+}
+
+fn opt<A: Array, T>(a: &A, i: usize, f: impl FnOnce() -> Result<T>) -> Result<Option<T>> {
+    match a.is_null(i) {
+        true => Ok(None),
+        false => f().map(Some),
+    }
+}
+
+fn int(batch: &RecordBatch, name: &str, i: usize) -> Result<Option<u64>> {
+    let a = ints(batch, name)?;
+    opt(a, i, || Ok(a.value(i) as u64))
+}
+
+fn bytes<'a>(batch: &'a RecordBatch, name: &str, i: usize) -> Result<Option<&'a [u8]>> {
+    let a = binary(batch, name)?;
+    opt(a, i, || Ok(a.value(i)))
+}
+
+fn hash(batch: &RecordBatch, name: &str, i: usize) -> Result<Option<FixedBytes<32>>> {
+    bytes(batch, name, i)?
+        .map(|b| FixedBytes::try_from(b).wrap_err_with(|| format!("{name} isn't 32 bytes")))
+        .transpose()
+}
+
+fn address(batch: &RecordBatch, name: &str, i: usize) -> Result<Option<Address>> {
+    bytes(batch, name, i)?
+        .map(|b| Address::try_from(b).wrap_err_with(|| format!("{name} isn't 20 bytes")))
+        .transpose()
+}
+
+fn numeric<T: std::str::FromStr>(batch: &RecordBatch, name: &str, i: usize) -> Result<Option<T>> {
+    let a = strings(batch, name)?;
+    opt(a, i, || {
+        a.value(i)
+            .parse()
+            .map_err(|_| eyre!("{name} {} isn't an integer", a.value(i)))
+    })
+}
+
+fn required<T>(v: Result<Option<T>>, name: &str) -> Result<T> {
+    v?.ok_or_else(|| eyre!("{name} is null"))
+}
+
+fn parse_blocks(batch: &RecordBatch) -> Result<Vec<jrpc::Block>> {
+    (0..batch.num_rows())
+        .map(|i| {
+            Ok(jrpc::Block {
+                number: U64::from(required(int(batch, "num", i), "num")?),
+                timestamp: U64::from(required(int(batch, "timestamp", i), "timestamp")?),
+                size: U64::from(required(int(batch, "size", i), "size")?),
+                gas_limit: required(numeric(batch, "gas_limit", i), "gas_limit")?,
+                gas_used: required(numeric(batch, "gas_used", i), "gas_used")?,
+                hash: required(hash(batch, "hash", i), "hash")?,
+                nonce: U256::try_from_be_slice(required(bytes(batch, "nonce", i), "nonce")?)
+                    .ok_or_else(|| eyre!("nonce is too large"))?,
+                receipts_root: required(hash(batch, "receipts_root", i), "receipts_root")?,
+                state_root: required(hash(batch, "state_root", i), "state_root")?,
+                extra_data: Bytes::copy_from_slice(required(
+                    bytes(batch, "extra_data", i),
+                    "extra_data",
+                )?),
+                miner: required(address(batch, "miner", i), "miner")?,
+                parent_hash: required(hash(batch, "parent_hash", i), "parent_hash")?,
+                logs_bloom: FixedBytes::try_from(required(
+                    bytes(batch, "logs_bloom", i),
+                    "logs_bloom",
+                )?)
+                .wrap_err("logs_bloom isn't 256 bytes")?,
+                base_fee_per_gas: numeric(batch, "base_fee_per_gas", i)?,
+                blob_gas_used: numeric(batch, "blob_gas_used", i)?,
+                excess_blob_gas: numeric(batch, "excess_blob_gas", i)?,
+                withdrawals_root: hash(batch, "withdrawals_root", i)?,
+                transactions: vec![],
+                withdrawals: vec![],
+            })
+        })
+        .collect()
+}
+
+/// An adapter column as the json the rpc would have returned
+fn extra(batch: &RecordBatch, c: &adapter::Column, i: usize) -> Result<Option<serde_json::Value>> {
+    Ok(match c.kind {
+        adapter::Kind::Bytes => {
+            bytes(batch, c.name, i)?.map(|b| serde_json::json!(Bytes::copy_from_slice(b)))
+        }
+        adapter::Kind::Numeric => numeric::<U256>(batch, c.name, i)?.map(|n| serde_json::json!(n)),
+        adapter::Kind::Json => {
+            let a = strings(batch, c.name)?;
+            opt(a, i, || {
+                serde_json::from_str(a.value(i)).wrap_err_with(|| format!("decoding {}", c.name))
+            })?
+        }
+    })
+}
+
+fn parse_txs(batch: &RecordBatch, adapter: &dyn adapter::Adapter) -> Result<Vec<(u64, jrpc::Tx)>> {
+    (0..batch.num_rows())
+        .map(|i| {
+            let block_num = required(int(batch, "block_num", i), "block_num")?;
+            let tx_hash = required(hash(batch, "hash", i), "hash")?;
+            let receipt = match numeric::<U256>(batch, "gas_used", i)? {
+                Some(gas_used) => Some(jrpc::Receipt {
+                    tx_hash,
+                    block_number: U64::from(block_num),
+                    status: int(batch, "status", i)?.map(U64::from),
+                    gas_used,
+                    cumulative_gas_used: required(
+                        numeric(batch, "cumulative_gas_used", i),
+                        "cumulative_gas_used",
+                    )?,
+                    effective_gas_price: numeric(batch, "effective_gas_price", i)?,
+                    contract_address: address(batch, "contract_address", i)?,
+                    logs_bloom: bytes(batch, "logs_bloom", i)?
+                        .map(FixedBytes::try_from)
+                        .transpose()
+                        .wrap_err("logs_bloom isn't 256 bytes")?
+                        .unwrap_or_default(),
+                    extra: Default::default(),
+                }),
+                None => None,
+            };
+            let calls = match strings(batch, "calls")?.is_null(i) {
+                true => None,
+                false => Some(
+                    serde_json::from_str(strings(batch, "calls")?.value(i))
+                        .wrap_err("decoding calls")?,
+                ),
+            };
+            let mut tx = jrpc::Tx {
+                ty: int(batch, "type", i)?.map(U64::from),
+                hash: tx_hash,
+                block_timestamp: None,
+                idx: U64::from(required(int(batch, "idx", i), "idx")?),
+                nonce: U256::try_from_be_slice(required(bytes(batch, "nonce", i), "nonce")?)
+                    .ok_or_else(|| eyre!("nonce is too large"))?,
+                from: required(address(batch, "from", i), "from")?,
+                to: address(batch, "to", i)?,
+                input: Bytes::copy_from_slice(required(bytes(batch, "input", i), "input")?),
+                value: required(numeric(batch, "value", i), "value")?,
+                gas: required(numeric(batch, "gas", i), "gas")?,
+                gas_price: numeric(batch, "gas_price", i)?,
+                calls,
+                fee_token: address(batch, "fee_token", i)?,
+                extra: Default::default(),
+                receipt,
+                trace: None,
+            };
+            for c in adapter.columns() {
+                let Some(v) = extra(batch, c, i)? else {
+                    continue;
+                };
+                match (c.source, tx.receipt.as_mut()) {
+                    (adapter::Source::Tx, _) => {
+                        tx.extra.insert(c.field.to_string(), v);
+                    }
+                    (adapter::Source::Receipt, Some(receipt)) => {
+                        receipt.extra.insert(c.field.to_string(), v);
+                    }
+                    (adapter::Source::Receipt, None) => {}
+                }
+            }
+            Ok((block_num, tx))
+        })
+        .collect()
+}
+
+fn parse_logs(batch: &RecordBatch) -> Result<Vec<jrpc::Log>> {
+    let topics = batch
+        .column_by_name("topics")
+        .ok_or_else(|| eyre!("missing column topics"))?
+        .as_list_opt::<i32>()
+        .ok_or_else(|| eyre!("column topics isn't a list"))?;
+    (0..batch.num_rows())
+        .map(|i| {
+            Ok(jrpc::Log {
+                block_number: U64::from(required(int(batch, "block_num", i), "block_num")?),
+                block_timestamp: None,
+                tx_hash: required(hash(batch, "tx_hash", i), "tx_hash")?,
+                log_idx: U64::from(required(int(batch, "log_idx", i), "log_idx")?),
+                address: FixedBytes::try_from(required(bytes(batch, "address", i), "address")?)
+                    .wrap_err("address isn't 20 bytes")?,
+                topics: topics
+                    .value(i)
+                    .as_binary::<i32>()
+                    .iter()
+                    .flatten()
+                    .map(|t| FixedBytes::try_from(t).wrap_err("topic isn't 32 bytes"))
+                    .collect::<Result<Vec<_>>>()?,
+                data: Bytes::copy_from_slice(required(bytes(batch, "data", i), "data")?),
+            })
+        })
+        .collect()
+}
+
+/// Copies a snapshot written by export into the chain's tables.
+/// The snapshot must have been exported from a chain with the same adapter.
+/// Existing data in the snapshot's range is replaced.
+/// Returns the snapshot's first and last block.
+pub async fn import(
+    pg: &mut tokio_postgres::Client,
+    chain: u64,
+    adapter: &dyn adapter::Adapter,
+    partition_blocks: u64,
+    dir: &Path,
+) -> Result<(u64, u64), sync::Error> {
+    let (mut blocks, metadata) = Rows::open(dir, "blocks", parse_blocks, |b| b.number.to())?;
+    let (mut txs, _) = Rows::open(dir, "txs", |batch| parse_txs(batch, adapter), |(n, _)| *n)?;
+    let (mut logs, _) = Rows::open(dir, "logs", parse_logs, |l| l.block_number.to())?;
+    let meta = |key: &str| -> Result<u64> {
+        metadata
+            .iter()
+            .find(|kv| kv.key == key)
+            .and_then(|kv| kv.value.as_ref())
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| eyre!("snapshot is missing {key}"))
+    };
+    let (from, to) = (meta("from")?, meta("to")?);
+    if meta("chain")? != chain {
+        return Err(eyre!("snapshot is for chain {} not {}", meta("chain")?, chain).into());
+    }
+    // snapshots from before adapters were exported have none of their columns
+    let snapshot_adapter = metadata
+        .iter()
+        .find(|kv| kv.key == "adapter")
+        .and_then(|kv| kv.value.as_deref())
+        .unwrap_or(adapter::Ethereum.name());
+    if snapshot_adapter != adapter.name() {
+        return Err(eyre!(
+            "snapshot is for adapter {} not {}",
+            snapshot_adapter,
+            adapter.name()
+        )
+        .into());
+    }
+    while let Some(mut batch) = blocks.next_batch()? {
+        let (first, last) = match (batch.first(), batch.last()) {
+            (Some(first), Some(last)) => (first.number.to::<u64>(), last.number.to::<u64>()),
+            _ => continue,
+        };
+        for (n, mut tx) in txs.take_through(last)? {
+            let block = batch
+                .iter_mut()
+                .find(|b| b.number.to::<u64>() == n)
+                .ok_or_else(|| eyre!("tx for missing block {n}"))?;
+            tx.block_timestamp = Some(block.timestamp);
+            block.transactions.push(tx);
+        }
+        let mut batch_logs = logs.take_through(last)?;
+        for log in batch_logs.iter_mut() {
+            let block = batch
+                .iter()
+                .find(|b| b.number == log.block_number)
+                .ok_or_else(|| eyre!("log for missing block {}", log.block_number))?;
+            log.block_timestamp = Some(block.timestamp);
+        }
+        let pgtx = pg.transaction().await?;
+        sync::setup_tables(&pgtx, chain, partition_blocks, first, last).await?;
+        sync::delete(&pgtx, chain, first, last).await?;
+        sync::copy_logs(&pgtx, api::Chain(chain), batch_logs, sync::CopyMode::Merge).await?;
+        sync::copy_txs(
+            &pgtx,
+            api::Chain(chain),
+            adapter,
+            &batch,
+            sync::CopyMode::Merge,
+        )
+        .await?;
+        sync::copy_blocks(&pgtx, api::Chain(chain), &batch, sync::CopyMode::Merge).await?;
+        pgtx.commit().await?;
+        tracing::info!("imported chain={} from={} to={}", chain, first, last);
+    }
+    Ok((from, to))
+}
@@ -10,11 +10,27 @@ create index if not exists txs_hash on txs(hash);
 create index if not exists txs_from on txs("from");
 create index if not exists txs_to on txs("to");
 create index if not exists txs_calls on txs using gin (calls);
+create index if not exists txs_contract_address
+on txs (contract_address)
+where contract_address is not null;
 
 create index if not exists txs_selector
 on txs (substring(input, 1, 4))
 where input is not null and octet_length(input) >= 4;
 
 
-create unique index if not exists blocks_chain_num on blocks(chain, num);
+create index if not exists withdrawals_block on withdrawals(block_num);
+create index if not exists withdrawals_address on withdrawals(address);
+
+create index if not exists traces_block on traces(block_num);
+create index if not exists traces_tx_hash on traces(tx_hash);
+create index if not exists traces_from on traces("from");
+create index if not exists traces_to on traces("to");
+
+create index if not exists traces_selector
+on traces (substring(input, 1, 4))
+where input is not null and octet_length(input) >= 4;
+
+
 create index if not exists blocks_timestamp on blocks(timestamp);
+
@@ -12,6 +12,10 @@ revoke execute on all functions in schema public from uapi;
 grant select on logs TO uapi;
 grant select on txs TO uapi;
 grant select on blocks TO uapi;
+grant select on traces TO uapi;
+grant select on withdrawals TO uapi;
+grant select on chain_state TO uapi;
+grant select on tokens TO uapi;
 
 alter role uapi set statement_timeout = '30s';
 alter role uapi set work_mem = '1GB';
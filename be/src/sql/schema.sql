@@ -34,6 +34,20 @@ create table if not exists txs (
     fee_token bytea,
     calls jsonb
 ) partition by list(chain);
+alter table txs add column if not exists status int2;
+alter table txs add column if not exists gas_used numeric;
+alter table txs add column if not exists cumulative_gas_used numeric;
+alter table txs add column if not exists effective_gas_price numeric;
+alter table txs add column if not exists contract_address bytea;
+alter table txs add column if not exists logs_bloom bytea;
+-- chain specific columns from adapter.rs
+alter table txs add column if not exists source_hash bytea;
+alter table txs add column if not exists mint numeric;
+alter table txs add column if not exists l1_fee numeric;
+alter table txs add column if not exists l1_gas_used numeric;
+alter table txs add column if not exists l1_gas_price numeric;
+alter table txs add column if not exists gas_used_for_l1 numeric;
+alter table txs add column if not exists fee_currency bytea;
 
 create table if not exists logs (
     chain int8 not null,
@@ -47,6 +61,87 @@ create table if not exists logs (
     data bytea not null
 ) partition by list(chain);
 
+alter table blocks add column if not exists parent_hash bytea;
+alter table blocks add column if not exists logs_bloom bytea;
+alter table blocks add column if not exists base_fee_per_gas numeric;
+alter table blocks add column if not exists blob_gas_used numeric;
+alter table blocks add column if not exists excess_blob_gas numeric;
+alter table blocks add column if not exists withdrawals_root bytea;
+
+create table if not exists withdrawals (
+    chain int8 not null,
+    block_num int8 not null,
+    block_timestamp timestamptz not null,
+    idx int8 not null,
+    validator_index int8 not null,
+    address bytea not null,
+    amount numeric not null
+) partition by list(chain);
+
+create table if not exists traces (
+    chain int8 not null,
+    block_num int8 not null,
+    block_timestamp timestamptz not null,
+    tx_idx int4 not null,
+    tx_hash bytea not null,
+    trace_idx int4 not null,
+    depth int4 not null,
+
+    call_type text not null,
+    "from" bytea not null,
+    "to" bytea not null,
+    value numeric not null,
+    input bytea not null,
+    output bytea,
+    gas numeric,
+    gas_used numeric,
+    error text
+) partition by list(chain);
+
+-- natural keys. sync merges copied rows on these so that
+-- copying a block range more than once doesn't duplicate rows.
+-- "on only" doesn't build anything. partitions created afterwards
+-- get the index and the repair unique-indexes command builds it
+-- on partitions that already have data.
+create unique index if not exists blocks_chain_num on only blocks(chain, num);
+create unique index if not exists txs_chain_block_idx on only txs(chain, block_num, idx);
+create unique index if not exists logs_chain_block_log_idx on only logs(chain, block_num, log_idx);
+create unique index if not exists traces_chain_block_trace_idx on only traces(chain, block_num, tx_idx, trace_idx);
+create unique index if not exists withdrawals_chain_block_idx on only withdrawals(chain, block_num, idx);
+
+create table if not exists chain_state (
+    chain int8 primary key,
+    safe int8,
+    finalized int8,
+    updated_at timestamptz not null default now()
+);
+alter table chain_state add column if not exists backfill_num int8;
+alter table chain_state add column if not exists backfill_to int8;
+alter table chain_state add column if not exists partial bool not null default false;
+alter table chain_state add column if not exists verified_num int8;
+-- downloader status for /chains
+alter table chain_state add column if not exists remote_num int8;
+alter table chain_state add column if not exists remote_timestamp timestamptz;
+alter table chain_state add column if not exists batch_size int4;
+alter table chain_state add column if not exists last_error text;
+alter table chain_state add column if not exists last_error_at timestamptz;
+alter table chain_state add column if not exists tokens_num int8;
+alter table chain_state add column if not exists tokens_from int8;
+
+-- eth_call results for contracts that emit Transfer logs.
+-- columns are null when the contract doesn't implement the function.
+create table if not exists tokens (
+    chain int8 not null,
+    address bytea not null,
+    name text,
+    symbol text,
+    decimals int4,
+    total_supply numeric,
+    updated_at timestamptz not null default now(),
+    primary key (chain, address)
+);
+create index if not exists tokens_updated_at on tokens(chain, updated_at);
+
 create or replace function b2i(data bytea) returns int4 as $$
 declare
 	n int4 = 0;
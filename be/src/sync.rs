@@ -2,21 +2,32 @@ use deadpool_postgres::Pool;
 use handlebars::{self, Handlebars};
 use itertools::Itertools;
 use shared::jrpc;
-use std::{collections::HashMap, fmt, sync::Arc};
+use std::{
+    collections::HashMap,
+    fmt,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use time::OffsetDateTime;
-use tokio::task::JoinHandle;
+use tokio::{sync::watch, task::JoinHandle, time::Instant};
 use url::Url;
 
-use alloy::primitives::{BlockHash, FixedBytes, U16, U256, U64};
+use alloy::primitives::{Address, BlockHash, FixedBytes, U16, U256, U64};
 use eyre::{eyre, Context, Result};
-use futures::pin_mut;
-use tokio_postgres::{binary_copy::BinaryCopyInWriter, Transaction};
+use futures::{pin_mut, StreamExt};
+use tokio_postgres::{binary_copy::BinaryCopyInWriter, types::ToSql, Transaction};
 
-use crate::{api, broadcast};
+use crate::{adapter, api, broadcast, leader, metrics, offline, partition, tokens, verify};
 
 #[derive(Debug)]
 pub enum Error {
     Wait,
+    Reorg(u64),
+    RateLimited(Option<Duration>),
     Retry(String),
     Fatal(eyre::Report),
 }
@@ -31,6 +42,8 @@ impl From<jrpc::Error> for Error {
     fn from(err: jrpc::Error) -> Self {
         if err.message == "no result" {
             Self::Wait
+        } else if err.rate_limited() {
+            Self::RateLimited(err.retry_after)
         } else {
             Self::Retry(format!("jrpc error {err:?}"))
         }
@@ -43,14 +56,53 @@ impl From<tokio_postgres::Error> for Error {
     }
 }
 
+/// What a chain stores. Chains that skip logs or txs,
+/// or that only store some logs, have partial data.
+/// Blocks are always stored since they are used to follow the chain.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Filter {
+    pub logs: bool,
+    pub txs: bool,
+    pub traces: bool,
+    pub log_filter: jrpc::LogFilter,
+    // the chain family's extra txs columns
+    pub adapter: &'static dyn adapter::Adapter,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter {
+            logs: true,
+            txs: true,
+            traces: false,
+            log_filter: jrpc::LogFilter::default(),
+            adapter: &adapter::Ethereum,
+        }
+    }
+}
+
+impl Filter {
+    pub fn partial(&self) -> bool {
+        !self.logs || !self.txs || !self.log_filter.is_empty()
+    }
+}
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct RemoteConfig {
     pub enabled: bool,
     pub chain: u64,
     pub url: Url,
+    pub urls: Vec<Url>,
+    pub ws_url: Option<Url>,
     pub start_block: Option<i64>,
     pub batch_size: u16,
     pub concurrency: u16,
+    pub filter: Filter,
+    pub backfill_to: Option<i64>,
+    pub partition_blocks: u64,
+    pub retention: Option<partition::Retention>,
+    pub verify: bool,
+    pub tokens: bool,
 }
 
 impl fmt::Display for RemoteConfig {
@@ -69,7 +121,31 @@ impl RemoteConfig {
             .get()
             .await?
             .query(
-                "select enabled, chain, url, start_block, batch_size, concurrency from config",
+                "
+                select
+                    enabled,
+                    chain,
+                    url,
+                    urls,
+                    ws_url,
+                    start_block,
+                    batch_size,
+                    concurrency,
+                    traces,
+                    logs,
+                    txs,
+                    addresses,
+                    topics,
+                    backfill_to,
+                    partition_blocks,
+                    retain_blocks,
+                    retain_days,
+                    retention,
+                    verify,
+                    tokens,
+                    adapter
+                from config
+                ",
                 &[],
             )
             .await?
@@ -81,12 +157,87 @@ impl RemoteConfig {
                     .get::<&str, String>("url")
                     .parse()
                     .expect("unable to parse url"),
+                urls: row
+                    .get::<&str, Vec<String>>("urls")
+                    .iter()
+                    .filter_map(|url| url.parse().ok())
+                    .collect(),
+                ws_url: row
+                    .get::<&str, Option<String>>("ws_url")
+                    .and_then(|url| url.parse().ok()),
                 start_block: row.get("start_block"),
                 batch_size: row.get::<&str, U16>("batch_size").to(),
                 concurrency: row.get::<&str, U16>("concurrency").to(),
+                filter: Filter {
+                    logs: row.get("logs"),
+                    txs: row.get("txs"),
+                    traces: row.get("traces"),
+                    log_filter: jrpc::LogFilter {
+                        addresses: row
+                            .get::<&str, Vec<Vec<u8>>>("addresses")
+                            .iter()
+                            .filter_map(|a| Address::try_from(a.as_slice()).ok())
+                            .collect(),
+                        topics: row
+                            .get::<&str, Vec<Vec<u8>>>("topics")
+                            .iter()
+                            .filter_map(|t| FixedBytes::<32>::try_from(t.as_slice()).ok())
+                            .collect(),
+                    },
+                    adapter: Self::adapter(row),
+                },
+                backfill_to: row.get("backfill_to"),
+                partition_blocks: row.get::<&str, i64>("partition_blocks").max(1) as u64,
+                retention: Self::retention(row),
+                verify: row.get("verify"),
+                tokens: row.get("tokens"),
             })
             .collect_vec())
     }
+
+    fn retention(row: &tokio_postgres::Row) -> Option<partition::Retention> {
+        let blocks = row
+            .get::<&str, Option<i64>>("retain_blocks")
+            .map(|n| n.max(0) as u64);
+        let days = row
+            .get::<&str, Option<i32>>("retain_days")
+            .map(|n| n.max(0) as u32);
+        if blocks.is_none() && days.is_none() {
+            return None;
+        }
+        let action: String = row.get("retention");
+        match partition::Action::parse(&action) {
+            Some(action) => Some(partition::Retention {
+                blocks,
+                days,
+                action,
+            }),
+            None => {
+                tracing::error!("unknown retention action {}", action);
+                None
+            }
+        }
+    }
+
+    fn adapter(row: &tokio_postgres::Row) -> &'static dyn adapter::Adapter {
+        let name: String = row.get("adapter");
+        adapter::get(&name).unwrap_or_else(|| {
+            tracing::error!("unknown adapter {}", name);
+            &adapter::Ethereum
+        })
+    }
+
+    /// A client for url followed by the additional urls
+    pub fn jrpc_client(&self) -> jrpc::Client {
+        let urls = std::iter::once(&self.url)
+            .chain(self.urls.iter())
+            .unique()
+            .map(Url::as_str)
+            .collect_vec();
+        jrpc::Client::with_urls(&urls)
+            .with_ws_url(self.ws_url.as_ref().map(Url::as_str))
+            .with_extra_fields(self.filter.adapter.extra_fields())
+    }
 }
 
 pub async fn test(url: &str, chain: u64) -> Result<(), shared::Error> {
@@ -101,8 +252,75 @@ pub async fn test(url: &str, chain: u64) -> Result<(), shared::Error> {
     }
 }
 
+/// Syncs the chain while holding its lock so that only one
+/// process writes a chain's blocks when be runs on many hosts.
+async fn sync_chain(
+    conf: RemoteConfig,
+    be_pool: Pool,
+    broadcaster: Arc<broadcast::Channel>,
+    archive_dir: Option<PathBuf>,
+) {
+    let chain = conf.chain;
+    let lock = match leader::Lock::acquire(&be_pool, chain).await {
+        Ok(lock) => lock,
+        Err(e) => {
+            tracing::error!("acquiring lock for chain {}: {:?}", chain, e);
+            return;
+        }
+    };
+    tracing::info!("leader for chain {}", chain);
+    metrics::leader(chain.into(), true);
+    let lock = Arc::new(lock);
+    let _local = broadcaster.local(chain);
+    let work = {
+        let lock = lock.clone();
+        async move {
+            if conf.url.scheme() == "file" {
+                match offline::Ingester::new(conf, be_pool, broadcaster, lock).await {
+                    Ok(ingester) => ingester.run().await,
+                    Err(e) => tracing::error!("offline ingest {:?}", e),
+                }
+                return;
+            }
+            let downloader = Downloader::new(conf, be_pool, broadcaster, archive_dir, lock);
+            let (backfiller, verifier, enricher) = (
+                downloader.backfiller(),
+                downloader.verifier(),
+                downloader.enricher(),
+            );
+            tokio::join!(
+                downloader.run(),
+                async {
+                    if let Some(backfiller) = backfiller {
+                        backfiller.run().await
+                    }
+                },
+                async {
+                    if let Some(verifier) = verifier {
+                        verifier.run().await
+                    }
+                },
+                async {
+                    if let Some(enricher) = enricher {
+                        enricher.run().await
+                    }
+                },
+            );
+        }
+    };
+    tokio::select! {
+        _ = work => {}
+        _ = lock.lost() => {}
+    }
+    metrics::leader(chain.into(), false);
+}
+
 pub async fn run(config: api::Config) {
     let mut table: HashMap<RemoteConfig, JoinHandle<()>> = HashMap::new();
+    let mut listener = config
+        .fe_url
+        .as_deref()
+        .map(|url| shared::pg::Listener::new(url, &["config"]));
     loop {
         let remotes = RemoteConfig::load(&config.fe_pool)
             .await
@@ -115,16 +333,15 @@ pub async fn run(config: api::Config) {
             .collect_vec();
         for remote in remotes.iter() {
             if !table.contains_key(remote) {
-                let (conf, be_pool, broadcaster) = (
+                let (conf, be_pool, broadcaster, archive_dir) = (
                     remote.clone(),
                     config.be_pool.clone(),
                     config.broadcaster.clone(),
+                    config.archive_dir.clone(),
                 );
                 table.insert(
                     conf.clone(),
-                    tokio::spawn(
-                        async move { Downloader::new(conf, be_pool, broadcaster).run().await },
-                    ),
+                    tokio::spawn(sync_chain(conf, be_pool, broadcaster, archive_dir)),
                 );
             }
         }
@@ -143,20 +360,97 @@ pub async fn run(config: api::Config) {
                 }
             }
         }
-        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+        match listener.as_mut() {
+            Some(listener) => {
+                listener.wait(Duration::from_secs(60)).await;
+            }
+            None => tokio::time::sleep(Duration::from_secs(5)).await,
+        }
+    }
+}
+
+/// Additive increase, multiplicative decrease batch sizing.
+/// Each successful download grows the batch by a tenth of the configured size
+/// and each failure halves it. Failures back off exponentially with jitter
+/// unless the provider asked for a specific delay with Retry-After.
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct BatchController {
+    pub size: u16,
+    pub max: u16,
+    pub failures: u32,
+    pub backoff_ms: u64,
+}
+
+impl BatchController {
+    const BASE_BACKOFF: Duration = Duration::from_millis(250);
+    const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+    pub fn new(max: u16) -> BatchController {
+        let max = max.max(1);
+        BatchController {
+            size: max,
+            max,
+            failures: 0,
+            backoff_ms: 0,
+        }
+    }
+
+    pub fn success(&mut self) {
+        self.failures = 0;
+        self.backoff_ms = 0;
+        self.size = self
+            .max
+            .min(self.size.saturating_add((self.max / 10).max(1)));
+    }
+
+    /// Shrinks the batch and returns how long to wait before the next download
+    pub fn failure(&mut self, retry_after: Option<Duration>) -> Duration {
+        self.failures = self.failures.saturating_add(1);
+        self.size = (self.size / 2).max(1);
+        let backoff = retry_after.unwrap_or_else(|| {
+            let exp = Self::BASE_BACKOFF * 2u32.pow(self.failures.min(8) - 1);
+            let cap = exp.min(Self::MAX_BACKOFF);
+            cap / 2 + cap.mul_f64(rand::random::<f64>() / 2.0)
+        });
+        self.backoff_ms = backoff.as_millis() as u64;
+        backoff
     }
 }
 
+const POLL_INTERVAL: Duration = Duration::from_secs(6);
+const RESUBSCRIBE_INTERVAL: Duration = Duration::from_secs(10);
+// download even without a new head in case the subscription is stuck
+const HEAD_TIMEOUT: Duration = Duration::from_secs(12);
+const RETENTION_INTERVAL: Duration = Duration::from_secs(600);
+
 pub struct Downloader {
     pub chain: api::Chain,
     pub batch_size: u16,
     pub concurrency: u16,
     pub start_block: Option<i64>,
+    pub filter: Filter,
+    pub partition_blocks: u64,
+    pub retention: Option<partition::Retention>,
+    pub verify: bool,
+    pub tokens: bool,
 
     be_pool: Pool,
     jrpc_client: Arc<jrpc::Client>,
     broadcaster: Arc<broadcast::Channel>,
+    lock: Arc<leader::Lock>,
     partition_max_block: Option<u64>,
+    polled_at: Option<Instant>,
+    retained_at: Option<Instant>,
+    archive_dir: Option<PathBuf>,
+    backfill_to: Option<u64>,
+    // true when the downloader is within one download of the remote's latest block
+    synced: Arc<AtomicBool>,
+    heads: Option<watch::Receiver<u64>>,
+    subscribed_at: Option<Instant>,
+    batch: BatchController,
+    // latest remote block's number and timestamp
+    remote: Option<(u64, u64)>,
+    last_error: Option<(String, OffsetDateTime)>,
 }
 
 impl Downloader {
@@ -164,18 +458,88 @@ impl Downloader {
         config: RemoteConfig,
         be_pool: Pool,
         broadcaster: Arc<broadcast::Channel>,
+        archive_dir: Option<PathBuf>,
+        lock: Arc<leader::Lock>,
     ) -> Downloader {
-        let jrpc_client = Arc::new(jrpc::Client::new(config.url.as_ref()));
+        let jrpc_client = Arc::new(config.jrpc_client());
         Downloader {
             chain: config.chain.into(),
             batch_size: config.batch_size,
             concurrency: config.concurrency,
             start_block: config.start_block,
+            filter: config.filter,
+            partition_blocks: config.partition_blocks,
+            retention: config.retention,
+            verify: config.verify,
+            tokens: config.tokens,
             be_pool,
             jrpc_client,
             broadcaster,
+            lock,
             partition_max_block: None,
+            polled_at: None,
+            retained_at: None,
+            archive_dir,
+            backfill_to: config.backfill_to.map(|n| n.max(0) as u64),
+            synced: Arc::new(AtomicBool::new(false)),
+            heads: None,
+            subscribed_at: None,
+            batch: BatchController::new(config.batch_size),
+            remote: None,
+            last_error: None,
+        }
+    }
+
+    pub fn backfiller(&self) -> Option<Backfiller> {
+        if self.backfill_to.is_some() && self.retention.is_some() {
+            tracing::warn!(
+                "ignoring backfill_to for chain {} with retention",
+                self.chain
+            );
+            return None;
         }
+        Some(Backfiller {
+            chain: self.chain,
+            batch_size: self.batch_size,
+            backfill_to: self.backfill_to?,
+            filter: self.filter.clone(),
+            partition_blocks: self.partition_blocks,
+            be_pool: self.be_pool.clone(),
+            jrpc_client: self.jrpc_client.clone(),
+            broadcaster: self.broadcaster.clone(),
+            lock: self.lock.clone(),
+            synced: self.synced.clone(),
+            partition_min_block: None,
+            batch: BatchController::new(self.batch_size),
+        })
+    }
+
+    pub fn verifier(&self) -> Option<verify::Verifier> {
+        self.verify.then(|| {
+            verify::Verifier::new(
+                self.chain,
+                self.filter.clone(),
+                self.be_pool.clone(),
+                self.jrpc_client.clone(),
+                self.broadcaster.clone(),
+                self.lock.clone(),
+            )
+        })
+    }
+
+    pub fn enricher(&self) -> Option<tokens::Enricher> {
+        if self.tokens && !self.filter.logs {
+            tracing::warn!("ignoring tokens for chain {} without logs", self.chain);
+            return None;
+        }
+        self.tokens.then(|| {
+            tokens::Enricher::new(
+                self.chain,
+                self.be_pool.clone(),
+                self.jrpc_client.clone(),
+                self.broadcaster.clone(),
+            )
+        })
     }
 
     async fn init_blocks(&mut self) -> Result<(), Error> {
@@ -195,21 +559,17 @@ impl Downloader {
             return Ok(());
         }
         let block = match self.start_block {
-            Some(n) => self.jrpc_client.block(format!("0x{:x}", n)).await?,
+            Some(n) => self.jrpc_client.block(format!("0x{n:x}")).await?,
             None => self.jrpc_client.block("latest".to_string()).await?,
         };
         tracing::info!("initializing blocks table at: {}", block.number);
         let mut pg = self.be_pool.get().await.wrap_err("getting pg")?;
         let pgtx = pg.transaction().await?;
-        self.partition_max_block = setup_tables(
-            &pgtx,
-            self.chain.0,
-            block.number.to(),
-            self.partition_max_block,
-        )
-        .await
-        .expect("setting up table for initial block");
-        copy_blocks(&pgtx, self.chain, &[block]).await?;
+        let n = block.number.to();
+        setup_tables(&pgtx, self.chain.0, self.partition_blocks, n, n)
+            .await
+            .expect("setting up table for initial block");
+        copy_blocks(&pgtx, self.chain, &[block], CopyMode::Insert).await?;
         pgtx.commit().await?;
         Ok(())
     }
@@ -220,53 +580,189 @@ impl Downloader {
             tracing::error!("init {:?}", e);
             return;
         }
-        let mut batch_size = self.batch_size;
+        if let Err(e) = record_filter(&self.be_pool, self.chain, &self.filter).await {
+            tracing::error!("recording filter {:?}", e);
+            return;
+        }
         loop {
-            match self.download(batch_size).await {
+            if self.polled_at.is_none_or(|t| t.elapsed() > POLL_INTERVAL) {
+                self.poll().await;
+            }
+            match self.download(self.batch.size).await {
                 Err(Error::Wait) => {
-                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    self.synced.store(true, Ordering::Relaxed);
+                    self.wait_for_head().await;
+                }
+                Err(Error::Reorg(depth)) => {
+                    tracing::warn!("reorg depth={}", depth);
+                    metrics::reorg(self.chain, depth);
+                }
+                Err(Error::RateLimited(retry_after)) => {
+                    let backoff = self.batch.failure(retry_after);
+                    tracing::warn!(
+                        "rate limited backoff={:?} batch={}",
+                        backoff,
+                        self.batch.size
+                    );
+                    self.error("rate limited".to_string());
+                    tokio::time::sleep(backoff).await;
                 }
                 Err(Error::Retry(err)) => {
-                    batch_size = std::cmp::max(1, batch_size / 10);
-                    tracing::error!("downloading error: {}", err);
-                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    let backoff = self.batch.failure(None);
+                    tracing::error!("downloading error: {} batch={}", err, self.batch.size);
+                    self.error(err);
+                    tokio::time::sleep(backoff).await;
                 }
                 Err(Error::Fatal(err)) => {
-                    batch_size = std::cmp::max(1, batch_size / 10);
-                    tracing::error!("fatal downloading error: {}", err);
-                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    let backoff = self.batch.failure(None);
+                    tracing::error!("fatal downloading error: {} batch={}", err, self.batch.size);
+                    self.error(err.to_string());
+                    tokio::time::sleep(backoff).await;
                 }
-                Ok(last) => {
-                    self.broadcaster.update(self.chain.0);
-                    let _ = self.broadcaster.json_updates.send(serde_json::json!({
-                        "new_block": "local",
-                        "chain": self.chain.0,
-                        "num": last,
-                    }));
-                    batch_size = self.batch_size
+                Ok(_) => self.batch.success(),
+            }
+        }
+    }
+
+    fn error(&mut self, err: String) {
+        self.last_error = Some((err, OffsetDateTime::now_utc()));
+    }
+
+    /// Waits for the remote to produce a new block. Uses the newHeads
+    /// subscription when the chain has a websocket url and falls back
+    /// to polling every second while the socket is down.
+    async fn wait_for_head(&mut self) {
+        if self.heads.is_none()
+            && self.jrpc_client.has_ws()
+            && self
+                .subscribed_at
+                .is_none_or(|t| t.elapsed() > RESUBSCRIBE_INTERVAL)
+        {
+            self.subscribed_at = Some(Instant::now());
+            match self.jrpc_client.new_heads().await {
+                Ok(heads) => self.heads = Some(heads),
+                Err(e) => tracing::warn!("subscribing to new heads: {}", e),
+            }
+        }
+        match self.heads.as_mut() {
+            Some(heads) => match tokio::time::timeout(HEAD_TIMEOUT, heads.changed()).await {
+                Ok(Ok(())) | Err(_) => {}
+                Ok(Err(_)) => {
+                    tracing::warn!("new heads subscription closed");
+                    self.heads = None;
                 }
+            },
+            None => tokio::time::sleep(std::time::Duration::from_secs(1)).await,
+        }
+    }
+
+    /// Periodic work that isn't tied to downloading blocks:
+    /// endpoint health, finality and retention.
+    async fn poll(&mut self) {
+        self.jrpc_client.probe().await;
+        let _ = self.broadcaster.json_updates.send(serde_json::json!({
+            "endpoints": self.jrpc_client.status(),
+            "batch": self.batch,
+            "chain": self.chain.0,
+        }));
+        if let Err(e) = self.update_state().await {
+            tracing::error!("updating chain state: {:?}", e);
+        }
+        self.polled_at = Some(Instant::now());
+        if self
+            .retained_at
+            .is_none_or(|t| t.elapsed() > RETENTION_INTERVAL)
+        {
+            self.retain().await;
+        }
+    }
+
+    async fn retain(&mut self) {
+        self.retained_at = Some(Instant::now());
+        let retention = match self.retention.clone() {
+            Some(retention) => retention,
+            None => return,
+        };
+        if let Err(e) = self.lock.check().await {
+            tracing::error!("applying retention: {:?}", e);
+            return;
+        }
+        match retention
+            .apply(&self.be_pool, self.chain.0, self.archive_dir.as_deref())
+            .await
+        {
+            Ok(removed) if !removed.is_empty() => {
+                // partitions the downloader created may have been removed
+                self.partition_max_block = None;
+                let _ = self.broadcaster.json_updates.send(serde_json::json!({
+                    "retention": removed,
+                    "chain": self.chain.0,
+                }));
             }
+            Ok(_) => {}
+            Err(e) => tracing::error!("applying retention: {:?}", e),
         }
     }
 
+    /// Records the remote's safe and finalized block numbers.
+    /// Chains that don't support these tags are stored as null.
+    /// Also records the downloader's status for /chains.
+    async fn update_state(&mut self) -> Result<(), Error> {
+        let (safe, finalized) = tokio::join!(
+            self.jrpc_client.header("safe"),
+            self.jrpc_client.header("finalized"),
+        );
+        let (safe, finalized) = (
+            safe.ok().map(|h| h.number),
+            finalized.ok().map(|h| h.number),
+        );
+        let remote_timestamp = self
+            .remote
+            .map(|(_, ts)| OffsetDateTime::from_unix_timestamp(ts as i64))
+            .transpose()
+            .wrap_err("remote timestamp")?;
+        self.be_pool
+            .get()
+            .await
+            .wrap_err("pg pool")?
+            .execute(
+                "
+                insert into chain_state(
+                    chain, safe, finalized,
+                    remote_num, remote_timestamp, batch_size,
+                    last_error, last_error_at, updated_at
+                )
+                values ($1, $2, $3, $4, $5, $6, $7, $8, now())
+                on conflict (chain) do update
+                set safe = excluded.safe,
+                    finalized = excluded.finalized,
+                    remote_num = excluded.remote_num,
+                    remote_timestamp = excluded.remote_timestamp,
+                    batch_size = excluded.batch_size,
+                    last_error = excluded.last_error,
+                    last_error_at = excluded.last_error_at,
+                    updated_at = excluded.updated_at
+                ",
+                &[
+                    &self.chain,
+                    &safe,
+                    &finalized,
+                    &self.remote.map(|(num, _)| num as i64),
+                    &remote_timestamp,
+                    &(self.batch.size as i32),
+                    &self.last_error.as_ref().map(|(err, _)| err),
+                    &self.last_error.as_ref().map(|(_, at)| at),
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
     async fn delete_after(&self, n: u64) -> Result<(), Error> {
         let mut pg = self.be_pool.get().await.wrap_err("pg pool")?;
         let pgtx = pg.transaction().await?;
-        pgtx.execute(
-            "delete from blocks where chain = $1 and num >= $2",
-            &[&self.chain, &U64::from(n)],
-        )
-        .await?;
-        pgtx.execute(
-            "delete from logs where chain = $1 and block_num >= $2",
-            &[&self.chain, &U64::from(n)],
-        )
-        .await?;
-        pgtx.execute(
-            "delete from txs where chain = $1 and block_num >= $2",
-            &[&self.chain, &U64::from(n)],
-        )
-        .await?;
+        delete(&pgtx, self.chain.0, n, i64::MAX as u64).await?;
+        self.lock.check().await?;
         pgtx.commit().await.wrap_err("unable to commit tx")?;
         Ok(())
     }
@@ -277,61 +773,110 @@ impl Downloader {
     If k <  n-2 we will make n-1-k requests to download n-1-k blocks
     and another request to download n-1-k logs.
 
-    After downloading n-k blocks/logs we check n's parent hash with k's hash.
-    If the hashes aren't equal we delete k's block/logs
-    and start the process over again.
+    The range k+1..n is capped at batch_size * concurrency and split
+    into batch_size sized ranges that are downloaded concurrently.
+    Ranges are committed in order as they arrive.
+
+    Before copying a range we check its first block's parent hash with
+    the hash of the previously committed block. If the hashes aren't equal
+    we walk back to the last block that matches the remote chain, delete
+    everything after it and start the process over again.
 
     If the hashes match, we copy blocks, transactions, and logs into their tables
     */
     #[tracing::instrument(level="info" skip_all, fields(from, to, blocks, txs, logs))]
     async fn download(&mut self, batch_size: u16) -> Result<u64, Error> {
         let latest = self.jrpc_client.block("latest".to_string()).await?;
+        self.remote = Some((latest.number.to(), latest.timestamp.to()));
         let _ = self.broadcaster.json_updates.send(serde_json::json!({
             "new_block": "remote",
             "chain": self.chain.0,
             "num": latest.number.to::<u64>(),
         }));
         let (local_num, local_hash) = self.local_latest().await?;
+        metrics::heads(self.chain, local_num, Some(latest.number.to()));
         if local_num >= latest.number.to() {
             return Err(Error::Wait);
         }
 
         let delta = latest.number.to::<u64>() - local_num;
-        let (from, to) = (local_num + 1, local_num + delta.min(batch_size as u64));
-        {
-            let mut pg = self.be_pool.get().await.wrap_err("pg pool")?;
-            let pgtx = pg.transaction().await?;
-            self.partition_max_block =
-                setup_tables(&pgtx, self.chain.0, to, self.partition_max_block).await?;
-            pgtx.commit().await.wrap_err("unable to commit tx")?;
-        }
+        let limit = batch_size as u64 * self.concurrency.max(1) as u64;
+        self.synced.store(delta <= limit, Ordering::Relaxed);
+        let (from, to) = (local_num + 1, local_num + delta.min(limit));
         tracing::Span::current()
             .record("from", from)
             .record("to", to);
-        let (mut blocks, mut logs) = (
-            self.jrpc_client.blocks(from, to).await?,
-            self.jrpc_client.logs(from, to).await?,
-        );
-        add_timestamp(&mut blocks, &mut logs);
-        validate_blocks(from, to, &blocks)?;
-        validate_logs(&blocks, &logs)?;
-        let (first_block, last_block) = (blocks.first().unwrap(), blocks.last().unwrap());
 
-        if first_block.parent_hash != local_hash {
-            self.delete_after(local_num).await?;
-            return Err(Error::Fatal(eyre!("reorg")));
+        let ranges = (from..=to)
+            .step_by(batch_size as usize)
+            .map(|n| (n, to.min(n + batch_size as u64 - 1)))
+            .collect_vec();
+        let (jrpc_client, filter) = (self.jrpc_client.clone(), &self.filter);
+        let mut downloads = futures::stream::iter(ranges)
+            .map(|(from, to)| {
+                let jrpc_client = jrpc_client.clone();
+                async move {
+                    let (blocks, logs) = fetch(&jrpc_client, filter, from, to).await?;
+                    Ok::<_, Error>((blocks, logs))
+                }
+            })
+            .buffered(self.concurrency.max(1) as usize);
+
+        let (mut num_blocks, mut num_txs, mut num_logs) = (0, 0, 0);
+        let (mut prev_num, mut prev_hash) = (local_num, local_hash);
+        while let Some(download) = downloads.next().await {
+            let (blocks, logs) = download?;
+            let (first_block, last_block) = (blocks.first().unwrap(), blocks.last().unwrap());
+            let (from, to) = (first_block.number.to(), last_block.number.to());
+            if first_block.parent_hash != prev_hash {
+                let ancestor = {
+                    let pg = self.be_pool.get().await.wrap_err("pg pool")?;
+                    let batch_size = self.batch_size as u64;
+                    common_ancestor(&pg, &self.jrpc_client, self.chain, batch_size, prev_num)
+                        .await?
+                };
+                self.delete_after(ancestor + 1).await?;
+                return Err(Error::Reorg(prev_num - ancestor));
+            }
+            if self.partition_max_block.is_none_or(|max| to > max) {
+                let mut pg = self.be_pool.get().await.wrap_err("pg pool")?;
+                let pgtx = pg.transaction().await?;
+                let partitions =
+                    setup_tables(&pgtx, self.chain.0, self.partition_blocks, from, to).await?;
+                pgtx.commit().await.wrap_err("unable to commit tx")?;
+                self.partition_max_block = partitions.last().map(|p| p.to - 1);
+            }
+            let mut pg = self.be_pool.get().await.wrap_err("pg pool")?;
+            let pgtx = pg.transaction().await?;
+            let (b, t, l) = copy(
+                &pgtx,
+                self.chain,
+                &self.filter,
+                &blocks,
+                logs,
+                CopyMode::Insert,
+            )
+            .await?;
+            broadcast::notify(&pgtx, self.chain.0, last_block.number.to()).await?;
+            self.lock.check().await?;
+            pgtx.commit().await.wrap_err("unable to commit tx")?;
+            (num_blocks, num_txs, num_logs) = (num_blocks + b, num_txs + t, num_logs + l);
+            (prev_num, prev_hash) = (last_block.number.to(), last_block.hash);
+            metrics::ingested(self.chain, b, t, l);
+            metrics::heads(self.chain, prev_num, Some(latest.number.to()));
+
+            self.broadcaster.update(self.chain.0);
+            let _ = self.broadcaster.json_updates.send(serde_json::json!({
+                "new_block": "local",
+                "chain": self.chain.0,
+                "num": prev_num,
+            }));
         }
-        let mut pg = self.be_pool.get().await.wrap_err("pg pool")?;
-        let pgtx = pg.transaction().await?;
-        let num_logs = copy_logs(&pgtx, self.chain, logs).await?;
-        let num_txs = copy_txs(&pgtx, self.chain, &blocks).await?;
-        let num_blocks = copy_blocks(&pgtx, self.chain, &blocks).await?;
-        pgtx.commit().await.wrap_err("unable to commit tx")?;
         tracing::Span::current()
             .record("blocks", num_blocks)
             .record("logs", num_logs)
             .record("txs", num_txs);
-        Ok(last_block.number.to())
+        Ok(prev_num)
     }
 
     async fn local_latest(&self) -> Result<(u64, BlockHash), Error> {
@@ -348,24 +893,413 @@ impl Downloader {
     }
 }
 
+/// Walks backward from the earliest local block to backfill_to.
+/// It only downloads while the tip follower is synced so that
+/// history never delays new blocks. Backfilled blocks are below
+/// the local latest block so they don't move query cursors.
+pub struct Backfiller {
+    pub chain: api::Chain,
+    pub batch_size: u16,
+    pub backfill_to: u64,
+    pub filter: Filter,
+    pub partition_blocks: u64,
+
+    be_pool: Pool,
+    jrpc_client: Arc<jrpc::Client>,
+    broadcaster: Arc<broadcast::Channel>,
+    lock: Arc<leader::Lock>,
+    synced: Arc<AtomicBool>,
+    partition_min_block: Option<u64>,
+    batch: BatchController,
+}
+
+impl Backfiller {
+    #[tracing::instrument(skip_all fields(event, chain = self.chain.0))]
+    pub async fn run(mut self) {
+        loop {
+            if !self.synced.load(Ordering::Relaxed) {
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                continue;
+            }
+            match self.download(self.batch.size).await {
+                Ok(None) => {
+                    tracing::info!("backfill complete to={}", self.backfill_to);
+                    return;
+                }
+                Ok(Some(_)) => self.batch.success(),
+                Err(Error::Wait) | Err(Error::Reorg(_)) => {
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                }
+                Err(Error::RateLimited(retry_after)) => {
+                    let backoff = self.batch.failure(retry_after);
+                    tracing::warn!("backfill rate limited backoff={:?}", backoff);
+                    tokio::time::sleep(backoff).await;
+                }
+                Err(Error::Retry(err)) => {
+                    let backoff = self.batch.failure(None);
+                    tracing::error!("backfill error: {} batch={}", err, self.batch.size);
+                    tokio::time::sleep(backoff).await;
+                }
+                Err(Error::Fatal(err)) => {
+                    let backoff = self.batch.failure(None);
+                    tracing::error!("fatal backfill error: {} batch={}", err, self.batch.size);
+                    tokio::time::sleep(backoff).await;
+                }
+            }
+        }
+    }
+
+    /// Downloads the batch below the earliest local block and
+    /// returns its first block. Returns None once backfill_to is reached.
+    #[tracing::instrument(level="info" skip_all, fields(from, to, blocks, txs, logs))]
+    async fn download(&mut self, batch_size: u16) -> Result<Option<u64>, Error> {
+        let (earliest, parent_hash) = self.local_earliest().await?;
+        if earliest <= self.backfill_to {
+            self.record_progress(earliest).await?;
+            return Ok(None);
+        }
+        let to = earliest - 1;
+        let from = to
+            .saturating_sub(batch_size.max(1) as u64 - 1)
+            .max(self.backfill_to);
+        tracing::Span::current()
+            .record("from", from)
+            .record("to", to);
+
+        let (blocks, logs) = fetch(&self.jrpc_client, &self.filter, from, to).await?;
+        let last_block = blocks.last().unwrap();
+        if last_block.hash != parent_hash {
+            return Err(Error::Fatal(eyre!(
+                "block {} hash {} doesn't match parent_hash {} of block {}",
+                to,
+                last_block.hash,
+                parent_hash,
+                earliest
+            )));
+        }
+        let mut pg = self.be_pool.get().await.wrap_err("pg pool")?;
+        if self.partition_min_block.is_none_or(|min| from < min) {
+            let pgtx = pg.transaction().await?;
+            let partitions =
+                setup_tables(&pgtx, self.chain.0, self.partition_blocks, from, to).await?;
+            pgtx.commit().await.wrap_err("unable to commit tx")?;
+            self.partition_min_block = partitions.first().map(|p| p.from);
+        }
+        let pgtx = pg.transaction().await?;
+        let (num_blocks, num_txs, num_logs) = copy(
+            &pgtx,
+            self.chain,
+            &self.filter,
+            &blocks,
+            logs,
+            CopyMode::Merge,
+        )
+        .await?;
+        self.lock.check().await?;
+        pgtx.commit().await.wrap_err("unable to commit tx")?;
+        metrics::ingested(self.chain, num_blocks, num_txs, num_logs);
+        self.record_progress(from).await?;
+        tracing::Span::current()
+            .record("blocks", num_blocks)
+            .record("logs", num_logs)
+            .record("txs", num_txs);
+        Ok(Some(from))
+    }
+
+    async fn record_progress(&self, num: u64) -> Result<(), Error> {
+        self.be_pool
+            .get()
+            .await
+            .wrap_err("pg pool")?
+            .execute(
+                "
+                insert into chain_state(chain, backfill_num, backfill_to, updated_at)
+                values ($1, $2, $3, now())
+                on conflict (chain) do update
+                set backfill_num = excluded.backfill_num,
+                    backfill_to = excluded.backfill_to,
+                    updated_at = excluded.updated_at
+                ",
+                &[&self.chain, &U64::from(num), &U64::from(self.backfill_to)],
+            )
+            .await?;
+        let _ = self.broadcaster.json_updates.send(serde_json::json!({
+            "backfill": "local",
+            "chain": self.chain.0,
+            "num": num,
+            "to": self.backfill_to,
+        }));
+        Ok(())
+    }
+
+    async fn local_earliest(&self) -> Result<(u64, BlockHash), Error> {
+        let pg = self.be_pool.get().await.wrap_err("pg pool")?;
+        let q =
+            "SELECT num, hash, parent_hash from blocks where chain = $1 order by num asc limit 1";
+        let row = match pg.query_opt(q, &[&self.chain]).await? {
+            Some(row) => row,
+            None => return Err(Error::Wait),
+        };
+        let num = row.try_get::<&str, i64>("num")? as u64;
+        match row.try_get::<&str, Option<BlockHash>>("parent_hash")? {
+            Some(parent_hash) => Ok((num, parent_hash)),
+            // blocks stored before parent_hash was recorded
+            None => match self.jrpc_client.headers(num, num).await?.first() {
+                Some(header) if header.hash == row.try_get::<&str, BlockHash>("hash")? => {
+                    Ok((num, header.parent_hash))
+                }
+                _ => Err(Error::Fatal(eyre!("earliest block {} not on remote", num))),
+            },
+        }
+    }
+}
+
+/// Marks the chain's data as partial so that query responses can say so.
+pub async fn record_filter(
+    be_pool: &Pool,
+    chain: api::Chain,
+    filter: &Filter,
+) -> Result<(), Error> {
+    be_pool
+        .get()
+        .await
+        .wrap_err("pg pool")?
+        .execute(
+            "
+            insert into chain_state(chain, partial, updated_at)
+            values ($1, $2, now())
+            on conflict (chain) do update
+            set partial = excluded.partial,
+                updated_at = excluded.updated_at
+            ",
+            &[&chain, &filter.partial()],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Downloads blocks and whatever the filter asks for in from..=to
+/// and checks that they are consistent with each other.
+async fn fetch(
+    jrpc_client: &jrpc::Client,
+    filter: &Filter,
+    from: u64,
+    to: u64,
+) -> Result<(Vec<jrpc::Block>, Vec<jrpc::Log>), Error> {
+    let logs = async {
+        match filter.logs {
+            true => jrpc_client.logs(from, to, &filter.log_filter).await,
+            false => Ok(vec![]),
+        }
+    };
+    let (mut blocks, mut logs) = tokio::try_join!(jrpc_client.blocks(from, to), logs)?;
+    let receipts = match filter.txs {
+        true => jrpc_client.receipts(&blocks).await?,
+        false => vec![],
+    };
+    let traces = match filter.traces {
+        true => jrpc_client.traces(from, to).await?,
+        false => vec![],
+    };
+    add_timestamp(&mut blocks, &mut logs);
+    if filter.txs {
+        add_receipts(&mut blocks, receipts)?;
+    }
+    add_traces(&mut blocks, traces)?;
+    validate_blocks(from, to, &blocks)?;
+    // the bloom check only holds when every log is downloaded
+    if filter.logs && filter.log_filter.is_empty() {
+        validate_logs(&blocks, &logs)?;
+    }
+    Ok((blocks, logs))
+}
+
+/// Copies everything downloaded for a range of blocks.
+/// Returns the number of blocks, txs and logs copied.
+pub async fn copy(
+    pgtx: &Transaction<'_>,
+    chain: api::Chain,
+    filter: &Filter,
+    blocks: &[jrpc::Block],
+    logs: Vec<jrpc::Log>,
+    mode: CopyMode,
+) -> Result<(u64, u64, u64), Error> {
+    let num_logs = copy_logs(pgtx, chain, logs, mode).await?;
+    let num_txs = match filter.txs {
+        true => copy_txs(pgtx, chain, filter.adapter, blocks, mode).await?,
+        false => 0,
+    };
+    copy_traces(pgtx, chain, blocks, mode).await?;
+    copy_withdrawals(pgtx, chain, blocks, mode).await?;
+    let num_blocks = copy_blocks(pgtx, chain, blocks, mode).await?;
+    Ok((num_blocks, num_txs, num_logs))
+}
+
+/// The highest local block at or below n whose hash matches the remote's.
+/// Remote headers are requested batch_size at a time.
+pub async fn common_ancestor(
+    pg: &tokio_postgres::Client,
+    client: &jrpc::Client,
+    chain: api::Chain,
+    batch_size: u64,
+    n: u64,
+) -> Result<u64, Error> {
+    let mut to = n;
+    loop {
+        let from = to.saturating_sub(batch_size.max(1) - 1);
+        let local: HashMap<u64, BlockHash> = pg
+            .query(
+                "select num, hash from blocks where chain = $1 and num >= $2 and num <= $3",
+                &[&chain, &U64::from(from), &U64::from(to)],
+            )
+            .await?
+            .iter()
+            .map(|row| (row.get::<&str, U64>("num").to(), row.get("hash")))
+            .collect();
+        if local.is_empty() {
+            return Err(Error::Fatal(eyre!("no common ancestor at or below {}", n)));
+        }
+        let remote = client.headers(from, to).await?;
+        if let Some(header) = remote
+            .iter()
+            .rev()
+            .find(|h| local.get(&h.number.to()) == Some(&h.hash))
+        {
+            return Ok(header.number.to());
+        }
+        if from == 0 {
+            return Err(Error::Fatal(eyre!("no common ancestor at or below {}", n)));
+        }
+        to = from - 1;
+    }
+}
+
+/// Deletes everything stored for blocks from..=to
+pub async fn delete(pgtx: &Transaction<'_>, chain: u64, from: u64, to: u64) -> Result<(), Error> {
+    for table in partition::TABLES {
+        let num = match table {
+            "blocks" => "num",
+            _ => "block_num",
+        };
+        pgtx.execute(
+            &format!("delete from {table} where chain = $1 and {num} >= $2 and {num} <= $3"),
+            &[&U64::from(chain), &U64::from(from), &U64::from(to)],
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Replaces block n with a fresh download in one transaction.
+/// Returns the number of logs copied.
 pub async fn sync_one(
     pg: &mut tokio_postgres::Client,
     client: &jrpc::Client,
+    lock: &leader::Lock,
     chain: u64,
+    filter: &Filter,
     n: u64,
 ) -> Result<u64, Error> {
-    let mut blocks = client.blocks(n, n).await?;
-    let mut logs = client.logs(n, n).await?;
-    add_timestamp(&mut blocks, &mut logs);
-    validate_blocks(n, n, &blocks)?;
-
+    let (blocks, logs) = fetch(client, filter, n, n).await?;
     let pgtx = pg.transaction().await?;
-    let num_logs = copy_logs(&pgtx, api::Chain(chain), logs).await?;
+    delete(&pgtx, chain, n, n).await?;
+    let (_, _, num_logs) = copy(
+        &pgtx,
+        api::Chain(chain),
+        filter,
+        &blocks,
+        logs,
+        CopyMode::Merge,
+    )
+    .await?;
+    lock.check().await?;
     pgtx.commit().await.wrap_err("unable to commit tx")?;
     Ok(num_logs)
 }
 
-fn validate_logs(blocks: &[jrpc::Block], logs: &[jrpc::Log]) -> Result<(), Error> {
+/// Replaces blocks from..=to with a fresh download in one transaction,
+/// fetching batch_size blocks at a time. The download must link to the
+/// stored blocks on both sides of the range, otherwise nothing is
+/// replaced and the range needs to be widened to include the reorg.
+/// Returns the number of blocks, txs and logs copied.
+pub async fn sync_range(
+    pg: &mut tokio_postgres::Client,
+    lock: &leader::Lock,
+    config: &RemoteConfig,
+    from: u64,
+    to: u64,
+) -> Result<(u64, u64, u64), Error> {
+    let (chain, client) = (config.chain, config.jrpc_client());
+    let before: Option<BlockHash> = match from.checked_sub(1) {
+        Some(n) => pg
+            .query_opt(
+                "select hash from blocks where chain = $1 and num = $2",
+                &[&U64::from(chain), &U64::from(n)],
+            )
+            .await?
+            .map(|row| row.get("hash")),
+        None => None,
+    };
+    let after: Option<BlockHash> = pg
+        .query_opt(
+            "select parent_hash from blocks where chain = $1 and num = $2",
+            &[&U64::from(chain), &U64::from(to + 1)],
+        )
+        .await?
+        .and_then(|row| row.get("parent_hash"));
+    let first = client.header(&format!("0x{from:x}")).await?;
+    let last = client.header(&format!("0x{to:x}")).await?;
+    if before.is_some_and(|h| h != first.parent_hash) {
+        return Err(Error::Fatal(eyre!(
+            "block {} parent_hash {} doesn't match stored block {} hash {}. widen the range",
+            from,
+            first.parent_hash,
+            from - 1,
+            before.unwrap(),
+        )));
+    }
+    if after.is_some_and(|h| h != last.hash) {
+        return Err(Error::Fatal(eyre!(
+            "block {} hash {} doesn't match stored block {} parent_hash {}. widen the range",
+            to,
+            last.hash,
+            to + 1,
+            after.unwrap(),
+        )));
+    }
+    let pgtx = pg.transaction().await?;
+    setup_tables(&pgtx, chain, config.partition_blocks, from, to).await?;
+    delete(&pgtx, chain, from, to).await?;
+    let (mut num_blocks, mut num_txs, mut num_logs) = (0, 0, 0);
+    let mut prev_hash = before;
+    for start in (from..=to).step_by(config.batch_size.max(1) as usize) {
+        let end = to.min(start + config.batch_size.max(1) as u64 - 1);
+        let (blocks, logs) = fetch(&client, &config.filter, start, end).await?;
+        let (first, last) = (blocks.first().unwrap(), blocks.last().unwrap());
+        if prev_hash.is_some_and(|h| h != first.parent_hash) {
+            return Err(Error::Retry(format!("reorg at block {start}")));
+        }
+        if end == to && after.is_some_and(|h| h != last.hash) {
+            return Err(Error::Retry(format!("reorg at block {to}")));
+        }
+        prev_hash = Some(last.hash);
+        let (b, t, l) = copy(
+            &pgtx,
+            api::Chain(chain),
+            &config.filter,
+            &blocks,
+            logs,
+            CopyMode::Merge,
+        )
+        .await?;
+        (num_blocks, num_txs, num_logs) = (num_blocks + b, num_txs + t, num_logs + l);
+    }
+    lock.check().await?;
+    pgtx.commit().await.wrap_err("unable to commit tx")?;
+    Ok((num_blocks, num_txs, num_logs))
+}
+
+pub fn validate_logs(blocks: &[jrpc::Block], logs: &[jrpc::Log]) -> Result<(), Error> {
     let mut logs_by_block: HashMap<U64, Vec<&jrpc::Log>> = HashMap::new();
     for log in logs {
         logs_by_block.entry(log.block_number).or_default().push(log);
@@ -382,7 +1316,7 @@ fn validate_logs(blocks: &[jrpc::Block], logs: &[jrpc::Log]) -> Result<(), Error
     Ok(())
 }
 
-fn validate_blocks(from: u64, to: u64, blocks: &[jrpc::Block]) -> Result<(), Error> {
+pub fn validate_blocks(from: u64, to: u64, blocks: &[jrpc::Block]) -> Result<(), Error> {
     if let Some(i) = blocks.first().map(|b| b.number.to::<u64>()) {
         if i != from {
             return Err(Error::Fatal(eyre!("want first block {} got {}", from, i)));
@@ -406,62 +1340,81 @@ fn validate_blocks(from: u64, to: u64, blocks: &[jrpc::Block]) -> Result<(), Err
     Ok(())
 }
 
+/// Creates the chain's tables and the range partitions needed to hold
+/// blocks from..=to. Returns all of the chain's block partitions.
+///
+/// The blocks partitions decide the bounds. The other tables get the
+/// same bounds separately so that a table added after a range's
+/// blocks partition was created still gets a partition for that range.
 //timescaledb
 pub async fn setup_tables(
     pgtx: &Transaction<'_>,
     chain: u64,
-    new_block: u64,
-    partition_max_block: Option<u64>,
-) -> Result<Option<u64>, Error> {
-    const N: u64 = 2000000;
-    let from = match partition_max_block {
-        Some(max) if new_block < max + 1 => return Ok(partition_max_block),
-        Some(max) => ((max + 1) / N) * N,
-        None => (new_block / N) * N,
-    };
-    let to = from + N;
-    let label = from / 1000000;
-    let query = Handlebars::new()
-        .render_template(
-            "
-            create table if not exists blocks_c{{chain}}
-            partition of blocks
-            for values in ({{chain}})
-            partition by range (num);
-
-            create table if not exists blocks_c{{chain}}_b{{label}}
-            partition of blocks_c{{chain}}
-            for values from ({{from}}) to ({{to}});
-
-            create table if not exists txs_c{{chain}}
-            partition of txs
-            for values in ({{chain}})
-            partition by range (block_num);
-
-            create table if not exists txs_c{{chain}}_b{{label}}
-            partition of txs_c{{chain}}
-            for values from ({{from}}) to ({{to}});
-            alter table txs_c{{chain}}_b{{label}} set (toast_tuple_target = 128);
-
-            create table if not exists logs_c{{chain}}
-            partition of logs
-            for values in ({{chain}})
-            partition by range (block_num);
-
-            create table if not exists logs_c{{chain}}_b{{label}}
-            partition of logs_c{{chain}}
-            for values from ({{from}}) to ({{to}});
-            alter table logs_c{{chain}}_b{{label}} set (toast_tuple_target = 128);
-            ",
-            &serde_json::json!({"chain": chain, "label": label, "from": from, "to": to,}),
-        )
-        .wrap_err("rendering sql template")?;
-    tracing::info!("new table range label={} from={} to={}", label, from, to);
-    pgtx.batch_execute(&query).await?;
-    Ok(Some(to - 1))
+    span: u64,
+    from: u64,
+    to: u64,
+) -> Result<Vec<partition::Partition>, Error> {
+    let mut partitions = partition::list(pgtx, &format!("blocks_c{chain}")).await?;
+    for (from, to) in partition::missing(&partitions, span, from, to) {
+        partitions.push(partition::Partition {
+            name: format!("blocks_c{chain}_b{}", partition::label(from)),
+            from,
+            to,
+        });
+    }
+    partitions.sort_by_key(|p| p.from);
+    let needed = partitions
+        .iter()
+        .filter(|p| p.to > from && p.from <= to)
+        .collect_vec();
+    for table in partition::TABLES {
+        let existing = partition::list(pgtx, &format!("{table}_c{chain}")).await?;
+        for p in &needed {
+            if existing.iter().any(|e| e.from < p.to && p.from < e.to) {
+                continue;
+            }
+            let label = partition::label(p.from);
+            let query = Handlebars::new()
+                .render_template(
+                    "
+                    create table if not exists {{table}}_c{{chain}}
+                    partition of {{table}}
+                    for values in ({{chain}})
+                    partition by range ({{num}});
+
+                    create table if not exists {{table}}_c{{chain}}_b{{label}}
+                    partition of {{table}}_c{{chain}}
+                    for values from ({{from}}) to ({{to}});
+                    {{#if toast}}
+                    alter table {{table}}_c{{chain}}_b{{label}} set (toast_tuple_target = 128);
+                    {{/if}}
+                    ",
+                    &serde_json::json!({
+                        "table": table,
+                        "chain": chain,
+                        "label": label,
+                        "from": p.from,
+                        "to": p.to,
+                        "num": if table == "blocks" { "num" } else { "block_num" },
+                        "toast": matches!(table, "txs" | "logs" | "traces"),
+                    }),
+                )
+                .wrap_err("rendering sql template")?;
+            tracing::info!(
+                "new table range table={} label={} from={} to={}",
+                table,
+                label,
+                p.from,
+                p.to
+            );
+            pgtx.batch_execute(&query).await?;
+        }
+    }
+    partition::check_unique_indexes(pgtx, chain).await?;
+    Ok(partitions)
 }
 
-fn add_timestamp(blocks: &mut [jrpc::Block], logs: &mut Vec<jrpc::Log>) {
+pub fn add_timestamp(blocks: &mut [jrpc::Block], logs: &mut Vec<jrpc::Log>) {
     for block in blocks.iter_mut() {
         for tx in block.transactions.iter_mut() {
             tx.block_timestamp = Some(block.timestamp);
@@ -475,26 +1428,69 @@ fn add_timestamp(blocks: &mut [jrpc::Block], logs: &mut Vec<jrpc::Log>) {
     }
 }
 
+fn add_receipts(blocks: &mut [jrpc::Block], receipts: Vec<jrpc::Receipt>) -> Result<(), Error> {
+    let mut indexed: HashMap<BlockHash, jrpc::Receipt> =
+        receipts.into_iter().map(|r| (r.tx_hash, r)).collect();
+    for block in blocks.iter_mut() {
+        for tx in block.transactions.iter_mut() {
+            match indexed.remove(&tx.hash) {
+                Some(receipt) => tx.receipt = Some(receipt),
+                None => {
+                    return Err(Error::Fatal(eyre!(
+                        "missing receipt for tx {} in block {}",
+                        tx.hash,
+                        block.number
+                    )))
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn add_traces(blocks: &mut [jrpc::Block], traces: Vec<jrpc::TxTrace>) -> Result<(), Error> {
+    let mut indexed: HashMap<BlockHash, jrpc::CallFrame> = HashMap::new();
+    for trace in traces {
+        match (trace.tx_hash, trace.result) {
+            (Some(hash), Some(frame)) => {
+                indexed.insert(hash, frame);
+            }
+            (hash, _) => tracing::warn!("skipping trace for {:?}: {:?}", hash, trace.error),
+        }
+    }
+    for block in blocks.iter_mut() {
+        for tx in block.transactions.iter_mut() {
+            tx.trace = indexed.remove(&tx.hash);
+        }
+    }
+    if let Some(hash) = indexed.keys().next() {
+        return Err(Error::Fatal(eyre!("trace for unknown tx {}", hash)));
+    }
+    Ok(())
+}
+
 #[tracing::instrument(level="debug" fields(chain) skip_all)]
 pub async fn copy_logs(
     pgtx: &Transaction<'_>,
     chain: api::Chain,
     logs: Vec<jrpc::Log>,
+    mode: CopyMode,
 ) -> Result<u64> {
-    const Q: &str = "
-        copy logs (
-            chain,
-            block_num,
-            block_timestamp,
-            log_idx,
-            tx_hash,
-            address,
-            topics,
-            data
-        )
-        from stdin binary
-    ";
-    let sink = pgtx.copy_in(Q).await.expect("unable to start copy in");
+    const COLUMNS: &str = r#"
+        chain,
+        block_num,
+        block_timestamp,
+        log_idx,
+        tx_hash,
+        address,
+        topics,
+        data
+    "#;
+    let q = format!(
+        "copy {} ({COLUMNS}) from stdin binary",
+        mode.target(pgtx, "logs").await?
+    );
+    let sink = pgtx.copy_in(&q).await.expect("unable to start copy in");
     let writer = BinaryCopyInWriter::new(
         sink,
         &[
@@ -526,17 +1522,19 @@ pub async fn copy_logs(
             ])
             .await?;
     }
-    writer.finish().await.wrap_err("unable to copy in logs")
+    let n = writer.finish().await.wrap_err("unable to copy in logs")?;
+    mode.finish(pgtx, "logs", COLUMNS, n).await
 }
 
 #[tracing::instrument(level="debug" fields(chain) skip_all)]
 pub async fn copy_txs(
     pgtx: &Transaction<'_>,
     chain: api::Chain,
+    adapter: &dyn adapter::Adapter,
     blocks: &[jrpc::Block],
+    mode: CopyMode,
 ) -> Result<u64> {
-    const Q: &str = r#"
-        copy txs (
+    const COLUMNS: &str = r#"
             chain,
             block_num,
             block_timestamp,
@@ -551,11 +1549,126 @@ pub async fn copy_txs(
             input,
             value,
             fee_token,
-            calls
-        )
-        from stdin binary
+            calls,
+            status,
+            gas_used,
+            cumulative_gas_used,
+            effective_gas_price,
+            contract_address,
+            logs_bloom
     "#;
-    let sink = pgtx.copy_in(Q).await.expect("unable to start copy in");
+    let extra = adapter.columns();
+    let columns = format!(
+        "{}{}",
+        COLUMNS,
+        extra.iter().map(|c| format!(", {}", c.name)).join("")
+    );
+    let q = format!(
+        "copy {} ({columns}) from stdin binary",
+        mode.target(pgtx, "txs").await?
+    );
+    let types = [
+        tokio_postgres::types::Type::INT8,
+        tokio_postgres::types::Type::INT8,
+        tokio_postgres::types::Type::TIMESTAMPTZ,
+        tokio_postgres::types::Type::INT4,
+        tokio_postgres::types::Type::INT2,
+        tokio_postgres::types::Type::NUMERIC,
+        tokio_postgres::types::Type::NUMERIC,
+        tokio_postgres::types::Type::BYTEA,
+        tokio_postgres::types::Type::BYTEA,
+        tokio_postgres::types::Type::BYTEA,
+        tokio_postgres::types::Type::BYTEA,
+        tokio_postgres::types::Type::BYTEA,
+        tokio_postgres::types::Type::NUMERIC,
+        tokio_postgres::types::Type::BYTEA,
+        tokio_postgres::types::Type::JSONB,
+        tokio_postgres::types::Type::INT2,
+        tokio_postgres::types::Type::NUMERIC,
+        tokio_postgres::types::Type::NUMERIC,
+        tokio_postgres::types::Type::NUMERIC,
+        tokio_postgres::types::Type::BYTEA,
+        tokio_postgres::types::Type::BYTEA,
+    ]
+    .into_iter()
+    .chain(extra.iter().map(|c| c.kind.pg_type()))
+    .collect_vec();
+    let sink = pgtx.copy_in(&q).await.expect("unable to start copy in");
+    let writer = BinaryCopyInWriter::new(sink, &types);
+    pin_mut!(writer);
+    for block in blocks {
+        for tx in &block.transactions {
+            let receipt = tx.receipt.as_ref();
+            let row: [&(dyn ToSql + Sync); 21] = [
+                &chain,
+                &block.number,
+                &OffsetDateTime::from_unix_timestamp(
+                    tx.block_timestamp.expect("missing tx ts").to::<u64>() as i64,
+                )?,
+                &tx.idx,
+                &tx.ty.unwrap_or(U64::from(0)),
+                &tx.gas,
+                &tx.gas_price.unwrap_or(U256::from(0)),
+                &tx.hash,
+                &tx.nonce,
+                &tx.from.to_vec(),
+                &tx.to.unwrap_or_default().to_vec(),
+                &tx.input.to_vec(),
+                &tx.value,
+                &tx.fee_token.as_ref().map(|a| a.to_vec()),
+                &tx.calls
+                    .as_ref()
+                    .filter(|v| !v.is_empty())
+                    .map(|v| tokio_postgres::types::Json(v.as_slice())),
+                &receipt.and_then(|r| r.status),
+                &receipt.map(|r| r.gas_used),
+                &receipt.map(|r| r.cumulative_gas_used),
+                &receipt.and_then(|r| r.effective_gas_price),
+                &receipt.and_then(|r| r.contract_address).map(|a| a.to_vec()),
+                &receipt.map(|r| r.logs_bloom.to_vec()),
+            ];
+            let extra = adapter.values(tx)?;
+            let values = row
+                .into_iter()
+                .chain(extra.iter().map(|v| v.as_ref() as &(dyn ToSql + Sync)))
+                .collect_vec();
+            writer.as_mut().write(&values).await?;
+        }
+    }
+    let n = writer.finish().await.wrap_err("unable to copy in txs")?;
+    mode.finish(pgtx, "txs", &columns, n).await
+}
+
+#[tracing::instrument(level="debug" fields(chain) skip_all)]
+pub async fn copy_traces(
+    pgtx: &Transaction<'_>,
+    chain: api::Chain,
+    blocks: &[jrpc::Block],
+    mode: CopyMode,
+) -> Result<u64> {
+    const COLUMNS: &str = r#"
+        chain,
+        block_num,
+        block_timestamp,
+        tx_idx,
+        tx_hash,
+        trace_idx,
+        depth,
+        call_type,
+        "from",
+        "to",
+        value,
+        input,
+        output,
+        gas,
+        gas_used,
+        error
+    "#;
+    let q = format!(
+        "copy {} ({COLUMNS}) from stdin binary",
+        mode.target(pgtx, "traces").await?
+    );
+    let sink = pgtx.copy_in(&q).await.expect("unable to start copy in");
     let writer = BinaryCopyInWriter::new(
         sink,
         &[
@@ -563,50 +1676,54 @@ pub async fn copy_txs(
             tokio_postgres::types::Type::INT8,
             tokio_postgres::types::Type::TIMESTAMPTZ,
             tokio_postgres::types::Type::INT4,
-            tokio_postgres::types::Type::INT2,
-            tokio_postgres::types::Type::NUMERIC,
-            tokio_postgres::types::Type::NUMERIC,
             tokio_postgres::types::Type::BYTEA,
+            tokio_postgres::types::Type::INT4,
+            tokio_postgres::types::Type::INT4,
+            tokio_postgres::types::Type::TEXT,
             tokio_postgres::types::Type::BYTEA,
             tokio_postgres::types::Type::BYTEA,
+            tokio_postgres::types::Type::NUMERIC,
             tokio_postgres::types::Type::BYTEA,
             tokio_postgres::types::Type::BYTEA,
             tokio_postgres::types::Type::NUMERIC,
-            tokio_postgres::types::Type::BYTEA,
-            tokio_postgres::types::Type::JSONB,
+            tokio_postgres::types::Type::NUMERIC,
+            tokio_postgres::types::Type::TEXT,
         ],
     );
     pin_mut!(writer);
     for block in blocks {
+        let timestamp = OffsetDateTime::from_unix_timestamp(block.timestamp.to())?;
         for tx in &block.transactions {
-            writer
-                .as_mut()
-                .write(&[
-                    &chain,
-                    &block.number,
-                    &OffsetDateTime::from_unix_timestamp(
-                        tx.block_timestamp.expect("missing tx ts").to::<u64>() as i64,
-                    )?,
-                    &tx.idx,
-                    &tx.ty.unwrap_or(U64::from(0)),
-                    &tx.gas,
-                    &tx.gas_price.unwrap_or(U256::from(0)),
-                    &tx.hash,
-                    &tx.nonce,
-                    &tx.from.to_vec(),
-                    &tx.to.unwrap_or_default().to_vec(),
-                    &tx.input.to_vec(),
-                    &tx.value,
-                    &tx.fee_token.as_ref().map(|a| a.to_vec()),
-                    &tx.calls
-                        .as_ref()
-                        .filter(|v| !v.is_empty())
-                        .map(|v| tokio_postgres::types::Json(v.as_slice())),
-                ])
-                .await?;
+            let Some(trace) = tx.trace.as_ref() else {
+                continue;
+            };
+            for (trace_idx, (depth, frame)) in trace.flatten().into_iter().enumerate() {
+                writer
+                    .as_mut()
+                    .write(&[
+                        &chain,
+                        &block.number,
+                        &timestamp,
+                        &tx.idx,
+                        &tx.hash,
+                        &(trace_idx as i32),
+                        &(depth as i32),
+                        &frame.ty,
+                        &frame.from.to_vec(),
+                        &frame.to.unwrap_or_default().to_vec(),
+                        &frame.value.unwrap_or_default(),
+                        &frame.input.to_vec(),
+                        &frame.output.as_ref().map(|o| o.to_vec()),
+                        &frame.gas,
+                        &frame.gas_used,
+                        &frame.error,
+                    ])
+                    .await?;
+            }
         }
     }
-    writer.finish().await.wrap_err("unable to copy in txs")
+    let n = writer.finish().await.wrap_err("unable to copy in traces")?;
+    mode.finish(pgtx, "traces", COLUMNS, n).await
 }
 
 #[tracing::instrument(level="debug" fields(chain) skip_all)]
@@ -614,25 +1731,33 @@ pub async fn copy_blocks(
     pgtx: &Transaction<'_>,
     chain: api::Chain,
     blocks: &[jrpc::Block],
+    mode: CopyMode,
 ) -> Result<u64> {
-    const Q: &str = r#"
-        copy blocks (
-            chain,
-            num,
-            timestamp,
-            size,
-            gas_limit,
-            gas_used,
-            hash,
-            nonce,
-            receipts_root,
-            state_root,
-            extra_data,
-            miner
-        )
-        from stdin binary
+    const COLUMNS: &str = r#"
+        chain,
+        num,
+        timestamp,
+        size,
+        gas_limit,
+        gas_used,
+        hash,
+        nonce,
+        receipts_root,
+        state_root,
+        extra_data,
+        miner,
+        parent_hash,
+        logs_bloom,
+        base_fee_per_gas,
+        blob_gas_used,
+        excess_blob_gas,
+        withdrawals_root
     "#;
-    let sink = pgtx.copy_in(Q).await.expect("unable to start copy in");
+    let q = format!(
+        "copy {} ({COLUMNS}) from stdin binary",
+        mode.target(pgtx, "blocks").await?
+    );
+    let sink = pgtx.copy_in(&q).await.expect("unable to start copy in");
     let writer = BinaryCopyInWriter::new(
         sink,
         &[
@@ -648,6 +1773,12 @@ pub async fn copy_blocks(
             tokio_postgres::types::Type::BYTEA,
             tokio_postgres::types::Type::BYTEA,
             tokio_postgres::types::Type::BYTEA,
+            tokio_postgres::types::Type::BYTEA,
+            tokio_postgres::types::Type::BYTEA,
+            tokio_postgres::types::Type::NUMERIC,
+            tokio_postgres::types::Type::NUMERIC,
+            tokio_postgres::types::Type::NUMERIC,
+            tokio_postgres::types::Type::BYTEA,
         ],
     );
     pin_mut!(writer);
@@ -667,8 +1798,162 @@ pub async fn copy_blocks(
                 &block.state_root,
                 &block.extra_data.to_vec(),
                 &block.miner.to_vec(),
+                &block.parent_hash,
+                &block.logs_bloom.to_vec(),
+                &block.base_fee_per_gas,
+                &block.blob_gas_used,
+                &block.excess_blob_gas,
+                &block.withdrawals_root,
             ])
             .await?;
     }
-    writer.finish().await.wrap_err("unable to copy in blocks")
+    let n = writer.finish().await.wrap_err("unable to copy in blocks")?;
+    mode.finish(pgtx, "blocks", COLUMNS, n).await
+}
+
+#[tracing::instrument(level="debug" fields(chain) skip_all)]
+pub async fn copy_withdrawals(
+    pgtx: &Transaction<'_>,
+    chain: api::Chain,
+    blocks: &[jrpc::Block],
+    mode: CopyMode,
+) -> Result<u64> {
+    const COLUMNS: &str = r#"
+        chain,
+        block_num,
+        block_timestamp,
+        idx,
+        validator_index,
+        address,
+        amount
+    "#;
+    let q = format!(
+        "copy {} ({COLUMNS}) from stdin binary",
+        mode.target(pgtx, "withdrawals").await?
+    );
+    let sink = pgtx.copy_in(&q).await.expect("unable to start copy in");
+    let writer = BinaryCopyInWriter::new(
+        sink,
+        &[
+            tokio_postgres::types::Type::INT8,
+            tokio_postgres::types::Type::INT8,
+            tokio_postgres::types::Type::TIMESTAMPTZ,
+            tokio_postgres::types::Type::INT8,
+            tokio_postgres::types::Type::INT8,
+            tokio_postgres::types::Type::BYTEA,
+            tokio_postgres::types::Type::NUMERIC,
+        ],
+    );
+    pin_mut!(writer);
+    for block in blocks {
+        let timestamp = OffsetDateTime::from_unix_timestamp(block.timestamp.to())?;
+        for withdrawal in &block.withdrawals {
+            writer
+                .as_mut()
+                .write(&[
+                    &chain,
+                    &block.number,
+                    &timestamp,
+                    &withdrawal.index,
+                    &withdrawal.validator_index,
+                    &withdrawal.address.to_vec(),
+                    &withdrawal.amount,
+                ])
+                .await?;
+        }
+    }
+    let n = writer
+        .finish()
+        .await
+        .wrap_err("unable to copy in withdrawals")?;
+    mode.finish(pgtx, "withdrawals", COLUMNS, n).await
+}
+
+/// How copied rows are written to their table
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CopyMode {
+    /// Rows are copied straight into the table. For blocks that
+    /// can't have been copied before, eg when following the tip.
+    Insert,
+    /// Rows are copied into a temporary table shaped like the table
+    /// and then merged into it. Rows that are already in the table
+    /// (by its unique natural key) are skipped so that copying a
+    /// block range twice can't create duplicates.
+    Merge,
+}
+
+impl CopyMode {
+    /// Returns the name of the table to copy into. The staging table
+    /// is created once per connection and is emptied on commit.
+    async fn target(&self, pgtx: &Transaction<'_>, table: &str) -> Result<String> {
+        match self {
+            CopyMode::Insert => Ok(table.to_string()),
+            CopyMode::Merge => {
+                pgtx.batch_execute(&format!(
+                    "create temp table if not exists {table}_staging (like {table}) on commit delete rows"
+                ))
+                .await
+                .wrap_err_with(|| format!("creating {table}_staging"))?;
+                Ok(format!("{table}_staging"))
+            }
+        }
+    }
+
+    /// Returns the number of new rows given the number of copied rows
+    async fn finish(
+        &self,
+        pgtx: &Transaction<'_>,
+        table: &str,
+        columns: &str,
+        n: u64,
+    ) -> Result<u64> {
+        if *self == CopyMode::Insert {
+            return Ok(n);
+        }
+        let n = pgtx
+            .execute(
+                &format!(
+                    "insert into {table} ({columns}) select {columns} from {table}_staging on conflict do nothing"
+                ),
+                &[],
+            )
+            .await
+            .wrap_err_with(|| format!("merging {table}_staging"))?;
+        // a transaction can merge more than once
+        pgtx.batch_execute(&format!("delete from {table}_staging"))
+            .await
+            .wrap_err_with(|| format!("emptying {table}_staging"))?;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::BatchController;
+
+    #[test]
+    fn test_batch_controller() {
+        let mut batch = BatchController::new(100);
+        let backoff = batch.failure(None);
+        assert_eq!(batch.size, 50);
+        assert!(backoff >= Duration::from_millis(125) && backoff <= Duration::from_millis(250));
+
+        let backoff = batch.failure(Some(Duration::from_secs(7)));
+        assert_eq!((batch.size, batch.failures), (25, 2));
+        assert_eq!(backoff, Duration::from_secs(7));
+
+        batch.success();
+        assert_eq!((batch.size, batch.failures, batch.backoff_ms), (35, 0, 0));
+        for _ in 0..10 {
+            batch.success();
+        }
+        assert_eq!(batch.size, 100);
+
+        for _ in 0..20 {
+            assert!(batch.failure(None) <= Duration::from_secs(30));
+        }
+        assert_eq!(batch.size, 1);
+    }
 }
@@ -0,0 +1,390 @@
+use std::{sync::Arc, time::Duration};
+
+use alloy::primitives::{b256, Address, Bytes, FixedBytes, U256, U64};
+use deadpool_postgres::Pool;
+use eyre::Context;
+use shared::jrpc;
+
+use crate::{api, broadcast, sync::Error};
+
+// Transfer(address,address,uint256). ERC20 and ERC721 share the signature.
+const TRANSFER: FixedBytes<32> =
+    b256!("ddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef");
+
+// name(), symbol(), decimals() and totalSupply()
+const SELECTORS: [[u8; 4]; 4] = [
+    [0x06, 0xfd, 0xde, 0x03],
+    [0x95, 0xd8, 0x9b, 0x41],
+    [0x31, 0x3c, 0xe5, 0x67],
+    [0x18, 0x16, 0x0d, 0xdd],
+];
+
+const SCAN_BLOCKS: u64 = 10000;
+// tokens per rpc batch. each token is one eth_call per selector.
+const BATCH: usize = 25;
+const REFRESH_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+const INTERVAL: Duration = Duration::from_secs(60);
+
+/// Fills the tokens table for contracts that emit Transfer logs.
+/// New addresses are found by scanning logs. chain_state.tokens_from
+/// and tokens_num are the scanned range, which grows forward with new
+/// blocks and backward when blocks are added below it (eg by backfill
+/// or a snapshot import). Known tokens are refreshed once they're
+/// older than REFRESH_AFTER.
+pub struct Enricher {
+    pub chain: api::Chain,
+
+    be_pool: Pool,
+    jrpc_client: Arc<jrpc::Client>,
+    broadcaster: Arc<broadcast::Channel>,
+}
+
+impl Enricher {
+    pub fn new(
+        chain: api::Chain,
+        be_pool: Pool,
+        jrpc_client: Arc<jrpc::Client>,
+        broadcaster: Arc<broadcast::Channel>,
+    ) -> Enricher {
+        Enricher {
+            chain,
+            be_pool,
+            jrpc_client,
+            broadcaster,
+        }
+    }
+
+    #[tracing::instrument(skip_all fields(event, chain = self.chain.0))]
+    pub async fn run(self) {
+        loop {
+            match self.step().await {
+                Ok(true) => {}
+                Ok(false) => tokio::time::sleep(INTERVAL).await,
+                Err(e) => {
+                    tracing::error!("enriching tokens: {:?}", e);
+                    tokio::time::sleep(INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    /// Returns false when there are no logs to scan and no stale tokens.
+    async fn step(&self) -> Result<bool, Error> {
+        let scanned = self.scan().await?;
+        let refreshed = self.refresh().await?;
+        Ok(scanned || refreshed)
+    }
+
+    /// Enriches the addresses that emitted Transfer logs in the next range
+    /// of blocks and aren't in tokens yet. Blocks after the scanned range
+    /// are scanned before blocks below it.
+    async fn scan(&self) -> Result<bool, Error> {
+        let pg = self.be_pool.get().await.wrap_err("pg pool")?;
+        let row = pg
+            .query_one(
+                "
+                select
+                    (select tokens_from from chain_state where chain = $1) as scanned_from,
+                    (select tokens_num from chain_state where chain = $1) as scanned_to,
+                    (select min(num) from blocks where chain = $1) as earliest,
+                    (select max(num) from blocks where chain = $1) as latest
+                ",
+                &[&self.chain],
+            )
+            .await?;
+        let (scanned_from, scanned_to, earliest, latest) = (
+            row.get::<&str, Option<i64>>("scanned_from"),
+            row.get::<&str, Option<i64>>("scanned_to"),
+            row.get::<&str, Option<i64>>("earliest"),
+            row.get::<&str, Option<i64>>("latest"),
+        );
+        let (earliest, latest) = match (earliest, latest) {
+            (Some(earliest), Some(latest)) => (earliest as u64, latest as u64),
+            _ => return Ok(false),
+        };
+        let (from, to) = match scanned_to.map(|n| n as u64) {
+            // ranges scanned before tokens_from was recorded start at tokens_num
+            Some(scanned_to) => {
+                let scanned_from = scanned_from.map_or(scanned_to + 1, |n| n as u64);
+                if scanned_to < latest {
+                    let from = earliest.max(scanned_to + 1);
+                    (from, latest.min(from + SCAN_BLOCKS - 1))
+                } else if earliest < scanned_from {
+                    let to = scanned_from - 1;
+                    (earliest.max(to.saturating_sub(SCAN_BLOCKS - 1)), to)
+                } else {
+                    return Ok(false);
+                }
+            }
+            None => (earliest, latest.min(earliest + SCAN_BLOCKS - 1)),
+        };
+        let addresses = pg
+            .query(
+                "
+                select distinct address
+                from logs
+                where chain = $1
+                and block_num >= $2
+                and block_num <= $3
+                and topics[1] = $4
+                and not exists (
+                    select 1 from tokens
+                    where tokens.chain = $1
+                    and tokens.address = logs.address
+                )
+                ",
+                &[&self.chain, &U64::from(from), &U64::from(to), &TRANSFER],
+            )
+            .await?
+            .iter()
+            .filter_map(|row| Address::try_from(row.get::<usize, &[u8]>(0)).ok())
+            .collect::<Vec<_>>();
+        for batch in addresses.chunks(BATCH) {
+            self.enrich(batch).await?;
+        }
+        pg.execute(
+            "
+            insert into chain_state(chain, tokens_from, tokens_num, updated_at)
+            values ($1, $2, $3, now())
+            on conflict (chain) do update
+            set tokens_from = least(chain_state.tokens_from, excluded.tokens_from),
+                tokens_num = greatest(chain_state.tokens_num, excluded.tokens_num),
+                updated_at = excluded.updated_at
+            ",
+            &[&self.chain, &U64::from(from), &U64::from(to)],
+        )
+        .await?;
+        let _ = self.broadcaster.json_updates.send(serde_json::json!({
+            "tokens": "local",
+            "chain": self.chain.0,
+            "num": to,
+            "new": addresses.len(),
+        }));
+        Ok(true)
+    }
+
+    async fn refresh(&self) -> Result<bool, Error> {
+        let addresses = self
+            .be_pool
+            .get()
+            .await
+            .wrap_err("pg pool")?
+            .query(
+                "
+                select address
+                from tokens
+                where chain = $1
+                and updated_at < now() - make_interval(secs => $2)
+                order by updated_at
+                limit $3
+                ",
+                &[&self.chain, &REFRESH_AFTER.as_secs_f64(), &(BATCH as i64)],
+            )
+            .await?
+            .iter()
+            .filter_map(|row| Address::try_from(row.get::<usize, &[u8]>(0)).ok())
+            .collect::<Vec<_>>();
+        if addresses.is_empty() {
+            return Ok(false);
+        }
+        self.enrich(&addresses).await?;
+        Ok(true)
+    }
+
+    async fn enrich(&self, addresses: &[Address]) -> Result<(), Error> {
+        let calls = addresses
+            .iter()
+            .flat_map(|a| SELECTORS.iter().map(|s| (*a, Bytes::copy_from_slice(s))))
+            .collect::<Vec<_>>();
+        let results = self.jrpc_client.calls(&calls).await?;
+        let mut pg = self.be_pool.get().await.wrap_err("pg pool")?;
+        let pgtx = pg.transaction().await?;
+        for (address, results) in addresses.iter().zip(results.chunks(SELECTORS.len())) {
+            let token = Token::decode(results);
+            pgtx.execute(
+                "
+                insert into tokens(chain, address, name, symbol, decimals, total_supply, updated_at)
+                values ($1, $2, $3, $4, $5, $6, now())
+                on conflict (chain, address) do update
+                set name = excluded.name,
+                    symbol = excluded.symbol,
+                    decimals = excluded.decimals,
+                    total_supply = excluded.total_supply,
+                    updated_at = excluded.updated_at
+                ",
+                &[
+                    &self.chain,
+                    &address.as_slice(),
+                    &token.name,
+                    &token.symbol,
+                    &token.decimals,
+                    &token.total_supply,
+                ],
+            )
+            .await?;
+        }
+        pgtx.commit().await.wrap_err("unable to commit tx")?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Token {
+    name: Option<String>,
+    symbol: Option<String>,
+    decimals: Option<i32>,
+    total_supply: Option<U256>,
+}
+
+impl Token {
+    /// results are in SELECTORS order
+    fn decode(results: &[Option<Bytes>]) -> Token {
+        let get = |i: usize| results.get(i).and_then(Option::as_ref).map(|b| &b[..]);
+        Token {
+            name: get(0).and_then(decode_string),
+            symbol: get(1).and_then(decode_string),
+            decimals: get(2)
+                .and_then(decode_uint)
+                .and_then(|d| u8::try_from(d).ok())
+                .map(i32::from),
+            total_supply: get(3).and_then(decode_uint),
+        }
+    }
+}
+
+fn word(data: &[u8], offset: usize) -> Option<usize> {
+    let word = data.get(offset..offset.checked_add(32)?)?;
+    usize::try_from(U256::from_be_slice(word)).ok()
+}
+
+fn decode_uint(data: &[u8]) -> Option<U256> {
+    data.get(..32).map(U256::from_be_slice)
+}
+
+/// Decodes an abi string. Some early tokens (eg MKR) return
+/// bytes32 instead so a single word is read as zero padded text.
+/// Postgres text can't contain nul bytes so they're removed.
+fn decode_string(data: &[u8]) -> Option<String> {
+    let bytes = if data.len() == 32 {
+        data
+    } else {
+        let start = word(data, 0)?.checked_add(32)?;
+        let len = word(data, start - 32)?;
+        data.get(start..start.checked_add(len)?)?
+    };
+    let s = String::from_utf8_lossy(bytes).replace('\0', "");
+    (!s.is_empty()).then_some(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use alloy::primitives::{Bytes, U256};
+    use shared::jrpc;
+
+    use super::{decode_string, Enricher, Token};
+    use crate::{api, broadcast, sync};
+
+    static SCHEMA: &str = include_str!("./sql/schema.sql");
+
+    fn abi_string(s: &str) -> Vec<u8> {
+        let mut data = U256::from(32).to_be_bytes::<32>().to_vec();
+        data.extend(U256::from(s.len()).to_be_bytes::<32>());
+        data.extend(s.as_bytes());
+        data.resize(64 + s.len().div_ceil(32) * 32, 0);
+        data
+    }
+
+    #[test]
+    fn test_decode_string() {
+        assert_eq!(
+            decode_string(&abi_string("USD Coin")),
+            Some("USD Coin".to_string())
+        );
+        let mut mkr = b"MKR".to_vec();
+        mkr.resize(32, 0);
+        assert_eq!(decode_string(&mkr), Some("MKR".to_string()));
+        assert_eq!(decode_string(&[]), None);
+        assert_eq!(decode_string(&[0; 32]), None);
+        // length past the end of the data
+        let mut bad = abi_string("USDC");
+        bad[63] = 0xff;
+        assert_eq!(decode_string(&bad), None);
+    }
+
+    #[test]
+    fn test_decode_token() {
+        let word = |n: u64| Some(Bytes::from(U256::from(n).to_be_bytes::<32>().to_vec()));
+        assert_eq!(
+            Token::decode(&[
+                Some(Bytes::from(abi_string("USD Coin"))),
+                Some(Bytes::from(abi_string("USDC"))),
+                word(6),
+                word(1000),
+            ]),
+            Token {
+                name: Some("USD Coin".to_string()),
+                symbol: Some("USDC".to_string()),
+                decimals: Some(6),
+                total_supply: Some(U256::from(1000)),
+            }
+        );
+        // an ERC721 without decimals and a contract that isn't a token
+        assert_eq!(Token::decode(&[None, None, None, word(1)]).decimals, None);
+        assert_eq!(
+            Token::decode(&[None, None, word(256), None]),
+            Token::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scan_below() {
+        let pool = shared::pg::test::new(SCHEMA).await;
+        let mut pg = pool.get().await.unwrap();
+        let pgtx = pg.transaction().await.unwrap();
+        sync::setup_tables(&pgtx, 1, 1000, 0, 999).await.unwrap();
+        pgtx.commit().await.unwrap();
+        let insert = |from: i64, to: i64| {
+            format!(
+                "
+                insert into blocks(chain, num, timestamp, gas_limit, gas_used, nonce, hash,
+                    receipts_root, state_root, extra_data, miner)
+                select 1, n, now(), 0, 0, '', '', '', '', '', ''
+                from generate_series({from}, {to}) n
+                "
+            )
+        };
+        let scanned = || async {
+            let row = pool
+                .get()
+                .await
+                .unwrap()
+                .query_one(
+                    "select tokens_from, tokens_num from chain_state where chain = 1",
+                    &[],
+                )
+                .await
+                .unwrap();
+            (row.get::<usize, i64>(0), row.get::<usize, i64>(1))
+        };
+        let enricher = Enricher::new(
+            api::Chain(1),
+            pool.clone(),
+            Arc::new(jrpc::Client::new("http://localhost:1")),
+            Arc::new(broadcast::Channel::default()),
+        );
+        pg.batch_execute(&insert(500, 510)).await.unwrap();
+        assert!(enricher.scan().await.unwrap());
+        assert_eq!(scanned().await, (500, 510));
+        assert!(!enricher.scan().await.unwrap());
+        // backfilled below the scanned range
+        pg.batch_execute(&insert(400, 499)).await.unwrap();
+        assert!(enricher.scan().await.unwrap());
+        assert_eq!(scanned().await, (400, 510));
+        pg.batch_execute(&insert(511, 520)).await.unwrap();
+        assert!(enricher.scan().await.unwrap());
+        assert_eq!(scanned().await, (400, 520));
+        assert!(!enricher.scan().await.unwrap());
+    }
+}
@@ -0,0 +1,452 @@
+use std::{collections::HashMap, fmt, sync::Arc, time::Duration};
+
+use alloy::{
+    consensus::{proofs::calculate_receipt_root, Eip658Value, Receipt, ReceiptEnvelope},
+    primitives::{Address, BlockHash, Bloom, Bytes, FixedBytes, Log, U256, U64},
+};
+use deadpool_postgres::Pool;
+use eyre::Context;
+use itertools::Itertools;
+use shared::jrpc;
+
+use crate::{
+    api, broadcast, leader,
+    sync::{self, Error, Filter},
+};
+
+/// A block whose stored data doesn't agree with itself
+#[derive(Debug, PartialEq, serde::Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Mismatch {
+    /// The bloom computed from the stored logs
+    Bloom { num: u64 },
+    /// The receipts root computed from the stored txs and logs
+    ReceiptsRoot {
+        num: u64,
+        stored: BlockHash,
+        computed: BlockHash,
+    },
+    /// The block's parent_hash isn't the hash of the stored previous block
+    ParentHash {
+        num: u64,
+        parent_hash: BlockHash,
+        prev_hash: BlockHash,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mismatch::Bloom { num } => write!(f, "block {num} logs don't match logs_bloom"),
+            Mismatch::ReceiptsRoot {
+                num,
+                stored,
+                computed,
+            } => write!(f, "block {num} receipts_root {stored} computed {computed}"),
+            Mismatch::ParentHash {
+                num,
+                parent_hash,
+                prev_hash,
+            } => write!(
+                f,
+                "block {num} parent_hash {parent_hash} previous block hash {prev_hash}"
+            ),
+        }
+    }
+}
+
+impl Mismatch {
+    pub fn num(&self) -> u64 {
+        match self {
+            Mismatch::Bloom { num }
+            | Mismatch::ReceiptsRoot { num, .. }
+            | Mismatch::ParentHash { num, .. } => *num,
+        }
+    }
+}
+
+// headers requested at a time while looking for a fork's common ancestor
+const ANCESTOR_BATCH: u64 = 100;
+
+/// Re-downloads the blocks that don't verify and returns their numbers.
+/// A block that doesn't verify by itself is downloaded again. A ParentHash
+/// mismatch means a fork was stored, so the blocks after the common ancestor
+/// are downloaded again up to the mismatch and on until the next stored
+/// block links to the last downloaded one.
+pub async fn repair(
+    pg: &mut tokio_postgres::Client,
+    client: &jrpc::Client,
+    lock: &leader::Lock,
+    chain: u64,
+    filter: &Filter,
+    mismatches: &[Mismatch],
+) -> Result<Vec<u64>, Error> {
+    let mut repaired: Vec<u64> = vec![];
+    for m in mismatches {
+        if repaired.contains(&m.num()) {
+            continue;
+        }
+        let mut n = match m {
+            Mismatch::ParentHash { num, .. } => {
+                let ancestor =
+                    sync::common_ancestor(pg, client, api::Chain(chain), ANCESTOR_BATCH, num - 1)
+                        .await?;
+                ancestor + 1
+            }
+            _ => m.num(),
+        };
+        loop {
+            tracing::warn!("re-downloading block {}", n);
+            sync::sync_one(pg, client, lock, chain, filter, n).await?;
+            repaired.push(n);
+            if n >= m.num() && links(pg, chain, n).await? {
+                break;
+            }
+            n += 1;
+        }
+    }
+    Ok(repaired)
+}
+
+/// True when block n + 1 isn't stored or its parent_hash is block n's hash
+async fn links(pg: &tokio_postgres::Client, chain: u64, n: u64) -> Result<bool, Error> {
+    let row = pg
+        .query_one(
+            "
+            select
+                (select hash from blocks where chain = $1 and num = $2) as hash,
+                (select parent_hash from blocks where chain = $1 and num = $2 + 1) as next
+            ",
+            &[&U64::from(chain), &U64::from(n)],
+        )
+        .await?;
+    let next: Option<BlockHash> = row.get("next");
+    Ok(next.is_none_or(|h| Some(h) == row.get::<&str, Option<BlockHash>>("hash")))
+}
+
+struct StoredBlock {
+    num: u64,
+    hash: BlockHash,
+    parent_hash: Option<BlockHash>,
+    logs_bloom: Option<Bloom>,
+    receipts_root: BlockHash,
+}
+
+struct StoredTx {
+    hash: BlockHash,
+    ty: i16,
+    status: Option<i16>,
+    cumulative_gas_used: Option<U256>,
+}
+
+/// Checks the stored blocks in from..=to against their logs and txs.
+/// The bloom is only checked when the filter stores every log
+/// and the receipts root is only checked when it also stores txs.
+/// Receipts that can't be rebuilt (pre-Byzantium or chain specific tx types)
+/// are skipped.
+pub async fn verify(
+    pg: &tokio_postgres::Client,
+    chain: u64,
+    filter: &Filter,
+    from: u64,
+    to: u64,
+) -> Result<Vec<Mismatch>, Error> {
+    let chain = U64::from(chain);
+    let blocks = pg
+        .query(
+            "
+            select num, hash, parent_hash, logs_bloom, receipts_root
+            from blocks
+            where chain = $1 and num >= $2 and num <= $3
+            order by num
+            ",
+            &[&chain, &U64::from(from.saturating_sub(1)), &U64::from(to)],
+        )
+        .await?
+        .iter()
+        .map(|row| StoredBlock {
+            num: row.get::<&str, U64>("num").to(),
+            hash: row.get("hash"),
+            parent_hash: row.get("parent_hash"),
+            logs_bloom: row
+                .get::<&str, Option<Vec<u8>>>("logs_bloom")
+                .and_then(|b| FixedBytes::<256>::try_from(b.as_slice()).ok())
+                .map(Bloom::from),
+            receipts_root: row.get("receipts_root"),
+        })
+        .collect_vec();
+    let mut logs: HashMap<u64, Vec<(BlockHash, Log)>> = HashMap::new();
+    for row in pg
+        .query(
+            "
+            select block_num, tx_hash, address, topics, data
+            from logs
+            where chain = $1 and block_num >= $2 and block_num <= $3
+            order by block_num, log_idx
+            ",
+            &[&chain, &U64::from(from), &U64::from(to)],
+        )
+        .await?
+    {
+        let topics = row
+            .get::<&str, Vec<Vec<u8>>>("topics")
+            .iter()
+            .filter_map(|t| FixedBytes::<32>::try_from(t.as_slice()).ok())
+            .collect_vec();
+        logs.entry(row.get::<&str, U64>("block_num").to())
+            .or_default()
+            .push((
+                row.get("tx_hash"),
+                Log::new_unchecked(
+                    Address::from_slice(&row.get::<&str, Vec<u8>>("address")),
+                    topics,
+                    Bytes::from(row.get::<&str, Vec<u8>>("data")),
+                ),
+            ));
+    }
+    let mut txs: HashMap<u64, Vec<StoredTx>> = HashMap::new();
+    if filter.txs {
+        for row in pg
+            .query(
+                "
+                select block_num, hash, type, status, cumulative_gas_used
+                from txs
+                where chain = $1 and block_num >= $2 and block_num <= $3
+                order by block_num, idx
+                ",
+                &[&chain, &U64::from(from), &U64::from(to)],
+            )
+            .await?
+        {
+            txs.entry(row.get::<&str, U64>("block_num").to())
+                .or_default()
+                .push(StoredTx {
+                    hash: row.get("hash"),
+                    ty: row.get("type"),
+                    status: row.get("status"),
+                    cumulative_gas_used: row.get("cumulative_gas_used"),
+                });
+        }
+    }
+
+    let all_logs = filter.logs && filter.log_filter.is_empty();
+    let mut mismatches = vec![];
+    for (prev, block) in std::iter::once(None)
+        .chain(blocks.iter().map(Some))
+        .tuple_windows()
+    {
+        let block = block.unwrap();
+        if block.num < from {
+            continue;
+        }
+        if let (Some(prev), Some(parent_hash)) = (prev, block.parent_hash) {
+            if prev.num + 1 == block.num && prev.hash != parent_hash {
+                mismatches.push(Mismatch::ParentHash {
+                    num: block.num,
+                    parent_hash,
+                    prev_hash: prev.hash,
+                });
+            }
+        }
+        if !all_logs {
+            continue;
+        }
+        let block_logs = logs.remove(&block.num).unwrap_or_default();
+        if let Some(stored) = block.logs_bloom {
+            let mut computed = Bloom::ZERO;
+            block_logs
+                .iter()
+                .for_each(|(_, log)| computed.accrue_log(log));
+            if computed != stored {
+                mismatches.push(Mismatch::Bloom { num: block.num });
+            }
+        }
+        if !filter.txs {
+            continue;
+        }
+        let block_txs = txs.remove(&block.num).unwrap_or_default();
+        if let Some(computed) = receipts_root(&block_txs, block_logs) {
+            if computed != block.receipts_root {
+                mismatches.push(Mismatch::ReceiptsRoot {
+                    num: block.num,
+                    stored: block.receipts_root,
+                    computed,
+                });
+            }
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Rebuilds the receipts trie root. Returns None when a
+/// receipt is missing the fields needed to encode it.
+fn receipts_root(txs: &[StoredTx], logs: Vec<(BlockHash, Log)>) -> Option<BlockHash> {
+    let mut logs_by_tx: HashMap<BlockHash, Vec<Log>> = HashMap::new();
+    for (tx_hash, log) in logs {
+        logs_by_tx.entry(tx_hash).or_default().push(log);
+    }
+    let mut receipts = vec![];
+    for tx in txs {
+        let receipt = Receipt {
+            status: Eip658Value::Eip658(tx.status? == 1),
+            cumulative_gas_used: tx.cumulative_gas_used?.try_into().ok()?,
+            logs: logs_by_tx.remove(&tx.hash).unwrap_or_default(),
+        }
+        .with_bloom();
+        receipts.push(match tx.ty {
+            0 => ReceiptEnvelope::Legacy(receipt),
+            1 => ReceiptEnvelope::Eip2930(receipt),
+            2 => ReceiptEnvelope::Eip1559(receipt),
+            3 => ReceiptEnvelope::Eip4844(receipt),
+            4 => ReceiptEnvelope::Eip7702(receipt),
+            _ => return None,
+        });
+    }
+    Some(calculate_receipt_root(&receipts))
+}
+
+const VERIFY_BLOCKS: u64 = 1000;
+const VERIFY_INTERVAL: Duration = Duration::from_secs(60);
+// pause between ranges so that catching up doesn't load the database
+const STEP_INTERVAL: Duration = Duration::from_secs(1);
+// blocks this close to the latest block may still be reorged
+const UNSAFE_BLOCKS: u64 = 64;
+
+/// Walks forward from the earliest local block verifying
+/// blocks that are finalized and re-downloads the bad ones.
+/// Progress is kept in chain_state.verified_num.
+pub struct Verifier {
+    pub chain: api::Chain,
+    pub filter: Filter,
+
+    be_pool: Pool,
+    jrpc_client: Arc<jrpc::Client>,
+    broadcaster: Arc<broadcast::Channel>,
+    lock: Arc<leader::Lock>,
+}
+
+impl Verifier {
+    pub fn new(
+        chain: api::Chain,
+        filter: Filter,
+        be_pool: Pool,
+        jrpc_client: Arc<jrpc::Client>,
+        broadcaster: Arc<broadcast::Channel>,
+        lock: Arc<leader::Lock>,
+    ) -> Verifier {
+        Verifier {
+            chain,
+            filter,
+            be_pool,
+            jrpc_client,
+            broadcaster,
+            lock,
+        }
+    }
+
+    #[tracing::instrument(skip_all fields(event, chain = self.chain.0))]
+    pub async fn run(self) {
+        loop {
+            match self.step().await {
+                Ok(true) => tokio::time::sleep(STEP_INTERVAL).await,
+                Ok(false) => tokio::time::sleep(VERIFY_INTERVAL).await,
+                Err(e) => {
+                    tracing::error!("verifying: {:?}", e);
+                    tokio::time::sleep(VERIFY_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    /// Verifies the next range. Returns false when there is nothing to verify.
+    async fn step(&self) -> Result<bool, Error> {
+        let mut pg = self.be_pool.get().await.wrap_err("pg pool")?;
+        let row = pg
+            .query_one(
+                "
+                select
+                    (select verified_num from chain_state where chain = $1) as verified,
+                    (select finalized from chain_state where chain = $1) as finalized,
+                    (select min(num) from blocks where chain = $1) as earliest,
+                    (select max(num) from blocks where chain = $1) as latest
+                ",
+                &[&self.chain],
+            )
+            .await?;
+        let (verified, finalized, earliest, latest) = (
+            row.get::<&str, Option<i64>>("verified"),
+            row.get::<&str, Option<i64>>("finalized"),
+            row.get::<&str, Option<i64>>("earliest"),
+            row.get::<&str, Option<i64>>("latest"),
+        );
+        let (earliest, latest) = match (earliest, latest) {
+            (Some(earliest), Some(latest)) => (earliest as u64, latest as u64),
+            _ => return Ok(false),
+        };
+        let limit = match finalized {
+            Some(n) => latest.min(n as u64),
+            None => latest.saturating_sub(UNSAFE_BLOCKS),
+        };
+        let from = verified.map_or(earliest, |n| earliest.max(n as u64 + 1));
+        let to = limit.min(from + VERIFY_BLOCKS - 1);
+        if from > to {
+            return Ok(false);
+        }
+        let mismatches = verify(&pg, self.chain.0, &self.filter, from, to).await?;
+        if !mismatches.is_empty() {
+            repair(
+                &mut pg,
+                &self.jrpc_client,
+                &self.lock,
+                self.chain.0,
+                &self.filter,
+                &mismatches,
+            )
+            .await?;
+        }
+        for m in mismatches.iter() {
+            tracing::warn!("{}", m);
+        }
+        pg.execute(
+            "
+            insert into chain_state(chain, verified_num, updated_at)
+            values ($1, $2, now())
+            on conflict (chain) do update
+            set verified_num = excluded.verified_num,
+                updated_at = excluded.updated_at
+            ",
+            &[&self.chain, &U64::from(to)],
+        )
+        .await?;
+        let _ = self.broadcaster.json_updates.send(serde_json::json!({
+            "verify": "local",
+            "chain": self.chain.0,
+            "num": to,
+            "mismatches": mismatches,
+        }));
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{receipts_root, StoredTx};
+    use alloy::primitives::{b256, U256};
+
+    #[test]
+    fn test_receipts_root() {
+        assert_eq!(
+            receipts_root(&[], vec![]),
+            Some(b256!(
+                "56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421"
+            ))
+        );
+        let missing_status = StoredTx {
+            hash: Default::default(),
+            ty: 2,
+            status: None,
+            cumulative_gas_used: Some(U256::from(21000)),
+        };
+        assert_eq!(receipts_root(&[missing_status], vec![]), None);
+    }
+}
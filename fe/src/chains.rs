@@ -1,3 +1,4 @@
+use alloy::primitives::{Address, B256};
 use eyre::Result;
 use serde::{Deserialize, Serialize};
 
@@ -12,14 +13,47 @@ pub struct Config {
     pub hidden: bool,
     pub chain: i64,
     pub start_block: Option<i64>,
+    pub backfill_to: Option<i64>,
     #[serde(skip_serializing)]
     pub url: String,
+    #[serde(default, skip_serializing)]
+    pub urls: Vec<String>,
+    #[serde(skip_serializing)]
+    pub ws_url: Option<String>,
+    #[serde(default = "default_enabled")]
+    pub logs: bool,
+    #[serde(default = "default_enabled")]
+    pub txs: bool,
+    #[serde(default)]
+    pub addresses: Vec<Address>,
+    #[serde(default)]
+    pub topics: Vec<B256>,
+    #[serde(default = "default_partition_blocks")]
+    pub partition_blocks: i64,
+    pub retain_blocks: Option<i64>,
+    pub retain_days: Option<i32>,
+    #[serde(default = "default_retention")]
+    pub retention: String,
+    #[serde(default = "default_adapter")]
+    pub adapter: String,
 }
 
 fn default_enabled() -> bool {
     true
 }
 
+fn default_partition_blocks() -> i64 {
+    2000000
+}
+
+fn default_retention() -> String {
+    String::from("detach")
+}
+
+fn default_adapter() -> String {
+    String::from("ethereum")
+}
+
 #[derive(Deserialize)]
 pub struct EnableRequest {
     pub chain: i64,
@@ -29,7 +63,7 @@ pub mod handlers {
     use super::{Config, EnableRequest};
     use crate::web;
     use axum::{extract::State, Json};
-    use be::sync;
+    use be::{adapter, sync};
     use rust_decimal::prelude::One;
 
     pub async fn enable(
@@ -81,18 +115,78 @@ pub mod handlers {
         State(state): State<web::State>,
         Json(req): Json<Config>,
     ) -> Result<(), shared::Error> {
-        sync::test(&req.url, req.chain as u64).await?;
+        if req.partition_blocks < 1 {
+            return Err(shared::Error::User(
+                "partition_blocks must be positive".into(),
+            ));
+        }
+        if !["detach", "drop", "archive"].contains(&req.retention.as_str()) {
+            return Err(shared::Error::User(format!(
+                "retention must be detach, drop or archive. got {}",
+                req.retention
+            )));
+        }
+        if adapter::get(&req.adapter).is_none() {
+            return Err(shared::Error::User(format!(
+                "adapter must be one of {}. got {}",
+                adapter::ALL
+                    .iter()
+                    .map(|a| a.name())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                req.adapter
+            )));
+        }
+        if req.backfill_to.is_some() && (req.retain_blocks.is_some() || req.retain_days.is_some()) {
+            return Err(shared::Error::User(
+                "backfill_to can't be used with retain_blocks or retain_days".into(),
+            ));
+        }
+        for url in std::iter::once(&req.url).chain(req.urls.iter()) {
+            sync::test(url, req.chain as u64).await?;
+        }
         let pg = state.pool.get().await?;
         pg.execute(
             "
-            insert into config(enabled, name, chain, url, start_block, provision_key)
-            values (true, $1, $2, $3, $4, $5)
+            insert into config(
+                enabled,
+                name,
+                chain,
+                url,
+                urls,
+                ws_url,
+                start_block,
+                backfill_to,
+                logs,
+                txs,
+                addresses,
+                topics,
+                partition_blocks,
+                retain_blocks,
+                retain_days,
+                retention,
+                adapter,
+                provision_key
+            )
+            values (true, $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)
             ",
             &[
                 &req.name,
                 &req.chain,
                 &req.url,
+                &req.urls,
+                &req.ws_url,
                 &req.start_block,
+                &req.backfill_to,
+                &req.logs,
+                &req.txs,
+                &req.addresses.iter().map(|a| a.to_vec()).collect::<Vec<_>>(),
+                &req.topics.iter().map(|t| t.to_vec()).collect::<Vec<_>>(),
+                &req.partition_blocks,
+                &req.retain_blocks,
+                &req.retain_days,
+                &req.retention,
+                &req.adapter,
                 &provision_key.secret,
             ],
         )
@@ -118,7 +212,30 @@ pub mod handlers {
 pub async fn list(pg: &tokio_postgres::Client) -> Result<Vec<Config>> {
     Ok(pg
         .query(
-            "select enabled, chain, name, url, start_block, popular, hidden from config order by chain",
+            "
+            select
+                enabled,
+                chain,
+                name,
+                url,
+                urls,
+                ws_url,
+                start_block,
+                backfill_to,
+                logs,
+                txs,
+                addresses,
+                topics,
+                partition_blocks,
+                retain_blocks,
+                retain_days,
+                retention,
+                adapter,
+                popular,
+                hidden
+            from config
+            order by chain
+            ",
             &[],
         )
         .await?
@@ -130,7 +247,27 @@ pub async fn list(pg: &tokio_postgres::Client) -> Result<Vec<Config>> {
             hidden: row.get("hidden"),
             chain: row.get("chain"),
             url: row.get("url"),
+            urls: row.get("urls"),
+            ws_url: row.get("ws_url"),
+            logs: row.get("logs"),
+            txs: row.get("txs"),
+            addresses: row
+                .get::<&str, Vec<Vec<u8>>>("addresses")
+                .iter()
+                .filter_map(|a| Address::try_from(a.as_slice()).ok())
+                .collect(),
+            topics: row
+                .get::<&str, Vec<Vec<u8>>>("topics")
+                .iter()
+                .filter_map(|t| B256::try_from(t.as_slice()).ok())
+                .collect(),
             start_block: row.get("start_block"),
+            backfill_to: row.get("backfill_to"),
+            partition_blocks: row.get("partition_blocks"),
+            retain_blocks: row.get("retain_blocks"),
+            retain_days: row.get("retain_days"),
+            retention: row.get("retention"),
+            adapter: row.get("adapter"),
         })
         .collect())
 }
@@ -98,7 +98,7 @@ create table if not exists collabs(
 drop view if exists account_limits;
 create view account_limits as
     with current_plans as (
-        select distinct on (owner_email) owner_email, rate, timeout, connections, queries
+        select distinct on (owner_email) owner_email, name, rate, timeout, connections, queries
         from plan_changes
         where (daimo_tx is not null or stripe_customer is not null)
         order by owner_email, created_at desc
@@ -106,6 +106,7 @@ create view account_limits as
     select
         current_plans.owner_email,
         secret,
+        current_plans.name as plan,
         timeout,
         rate,
         connections,
@@ -116,7 +117,7 @@ create view account_limits as
     inner join current_plans on current_plans.owner_email = api_keys.owner_email
     where api_keys.deleted_at is null
     union all
-    select org, secret, 10, 10, 1000, 500000, 1000, coalesce(origins, '{}')
+    select org, secret, 'wl', 10, 10, 1000, 500000, 1000, coalesce(origins, '{}')
     from wl_api_keys
     where deleted_at is null;
 
@@ -161,6 +162,21 @@ create table if not exists config (
     hidden bool default false,
     provision_key text
 );
+alter table config add column if not exists traces bool not null default false;
+alter table config add column if not exists backfill_to int8;
+alter table config add column if not exists urls text[] not null default '{}';
+alter table config add column if not exists ws_url text;
+alter table config add column if not exists logs bool not null default true;
+alter table config add column if not exists txs bool not null default true;
+alter table config add column if not exists addresses bytea[] not null default '{}';
+alter table config add column if not exists topics bytea[] not null default '{}';
+alter table config add column if not exists partition_blocks int8 not null default 2000000;
+alter table config add column if not exists retain_blocks int8;
+alter table config add column if not exists retain_days int4;
+alter table config add column if not exists retention text not null default 'detach';
+alter table config add column if not exists adapter text not null default 'ethereum';
+alter table config add column if not exists verify bool not null default false;
+alter table config add column if not exists tokens bool not null default false;
 
 insert into
     config(enabled, chain, name, url)
@@ -188,3 +204,35 @@ insert into
         (false, 52085143,   'Ble Testnet',          'https://rpc-ethena-testnet-0.t.conduit.xyz')
     on conflict(chain)
     do nothing;
+
+-- be listens on these channels and reloads right away
+-- instead of waiting for its next poll
+create or replace function notify_config() returns trigger as $$
+begin
+    perform pg_notify('config', '');
+    return null;
+end;
+$$ language plpgsql;
+
+create or replace function notify_account_limits() returns trigger as $$
+begin
+    perform pg_notify('account_limits', '');
+    return null;
+end;
+$$ language plpgsql;
+
+create or replace trigger config_notify
+after insert or update or delete on config
+for each statement execute function notify_config();
+
+create or replace trigger api_keys_notify
+after insert or update or delete on api_keys
+for each statement execute function notify_account_limits();
+
+create or replace trigger wl_api_keys_notify
+after insert or update or delete on wl_api_keys
+for each statement execute function notify_account_limits();
+
+create or replace trigger plan_changes_notify
+after insert or update or delete on plan_changes
+for each statement execute function notify_account_limits();
@@ -1,13 +1,28 @@
 use alloy::primitives::{Address, BlockHash, Bytes, FixedBytes, U256, U64};
+use futures::{SinkExt, StreamExt};
 use itertools::Itertools;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::value::RawValue;
+use tokio::sync::watch;
+use tokio_tungstenite::tungstenite::Message;
 
-use std::{fmt, time::Duration};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 #[derive(Clone, Deserialize, Debug)]
 pub struct Error {
     pub code: i64,
     pub message: String,
+    // from the Retry-After header of a 429 response
+    #[serde(skip)]
+    pub retry_after: Option<Duration>,
 }
 
 impl fmt::Display for Error {
@@ -16,11 +31,73 @@ impl fmt::Display for Error {
     }
 }
 
+impl Error {
+    /// The provider doesn't implement the requested method
+    pub fn unsupported(&self) -> bool {
+        let message = self.message.to_lowercase();
+        self.code == -32601
+            || message.contains("not supported")
+            || message.contains("not available")
+            || message.contains("does not exist")
+            || message.contains("method not found")
+    }
+
+    /// The provider is throttling requests. Either with an HTTP 429
+    /// or with a JSON-RPC error in a successful response.
+    pub fn rate_limited(&self) -> bool {
+        let message = self.message.to_lowercase();
+        self.code == 429
+            || message.contains("rate limit")
+            || message.contains("too many requests")
+            || message.contains("request rate exceeded")
+            || message.contains("compute units")
+    }
+
+    /// The provider rejected eth_getLogs because the block range
+    /// or the number of results exceeds its limits. -32005 is the
+    /// EIP-1474 limit exceeded code, which some providers also use
+    /// for rate limits. The rest are the providers' own messages.
+    pub fn too_many_logs(&self) -> bool {
+        if self.code == -32005 && !self.rate_limited() {
+            return true;
+        }
+        let message = self.message.to_lowercase();
+        [
+            "query returned more than",
+            "query exceeds max results",
+            "exceed maximum block range",
+            "exceeds max block range",
+            "block range too large",
+            "block range is too large",
+            "block range is too wide",
+            "block range limit exceeded",
+            "log response size exceeded",
+            "eth_getlogs is limited to",
+            "eth_getlogs and eth_newfilter are limited to",
+            "maximum allowed number of requested blocks",
+            "logs over more than",
+        ]
+        .iter()
+        .any(|pattern| message.contains(pattern))
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(err: tokio_tungstenite::tungstenite::Error) -> Self {
+        Error {
+            code: -1,
+            message: format!("websocket: {err}"),
+            retry_after: None,
+        }
+    }
+}
+
 impl From<reqwest::Error> for Error {
     fn from(err: reqwest::Error) -> Self {
         Error {
             code: err.status().unwrap_or_default().as_u16() as i64,
             message: err.to_string(),
+            retry_after: None,
         }
     }
 }
@@ -41,6 +118,27 @@ pub struct Log {
     pub data: Bytes,
 }
 
+/// Restricts eth_getLogs to logs emitted by one of the addresses
+/// with one of the topics as topic0. Empty lists match everything.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct LogFilter {
+    pub addresses: Vec<Address>,
+    pub topics: Vec<FixedBytes<32>>,
+}
+
+impl LogFilter {
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty() && self.topics.is_empty()
+    }
+
+    /// Applies the filter to a log the way eth_getLogs would
+    pub fn matches(&self, log: &Log) -> bool {
+        (self.addresses.is_empty() || self.addresses.iter().any(|a| a.0 == log.address))
+            && (self.topics.is_empty()
+                || log.topics.first().is_some_and(|t| self.topics.contains(t)))
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Call {
     pub from: Option<Address>,
@@ -72,6 +170,129 @@ pub struct Tx {
     pub calls: Option<Vec<Call>>,
     #[serde(rename = "feeToken")]
     pub fee_token: Option<Address>,
+    /// Fields that aren't common to every chain (eg OP-stack's sourceHash).
+    /// Only the ExtraFields the client was built with are collected.
+    #[serde(skip)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+    #[serde(skip)]
+    pub receipt: Option<Receipt>,
+    #[serde(skip)]
+    pub trace: Option<CallFrame>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Receipt {
+    #[serde(rename = "transactionHash")]
+    pub tx_hash: BlockHash,
+    #[serde(rename = "blockNumber")]
+    pub block_number: U64,
+    pub status: Option<U64>,
+    #[serde(rename = "gasUsed")]
+    pub gas_used: U256,
+    #[serde(rename = "cumulativeGasUsed")]
+    pub cumulative_gas_used: U256,
+    #[serde(rename = "effectiveGasPrice")]
+    pub effective_gas_price: Option<U256>,
+    #[serde(rename = "contractAddress")]
+    pub contract_address: Option<Address>,
+    #[serde(rename = "logsBloom")]
+    pub logs_bloom: FixedBytes<256>,
+    /// Fields that aren't common to every chain (eg Arbitrum's gasUsedForL1).
+    /// Only the ExtraFields the client was built with are collected.
+    #[serde(skip)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Names of the fields to collect into Tx.extra and Receipt.extra.
+/// Collecting them takes a second pass over the json
+/// so it's only done for chains that store them.
+#[derive(Clone, Debug, Default)]
+pub struct ExtraFields {
+    pub tx: Vec<&'static str>,
+    pub receipt: Vec<&'static str>,
+}
+
+#[derive(Deserialize)]
+struct RawTxs<'a> {
+    #[serde(borrow)]
+    transactions: Vec<HashMap<String, &'a RawValue>>,
+}
+
+impl ExtraFields {
+    pub fn is_empty(&self) -> bool {
+        self.tx.is_empty() && self.receipt.is_empty()
+    }
+
+    /// Decodes a block with its transactions
+    pub fn block(&self, json: &str) -> Result<Block, serde_json::Error> {
+        let mut block: Block = serde_json::from_str(json)?;
+        if !self.tx.is_empty() {
+            let raw: RawTxs = serde_json::from_str(json)?;
+            for (tx, fields) in block.transactions.iter_mut().zip(raw.transactions) {
+                tx.extra = pick(&self.tx, fields)?;
+            }
+        }
+        Ok(block)
+    }
+
+    pub fn receipt(&self, json: &str) -> Result<Receipt, serde_json::Error> {
+        let mut receipt: Receipt = serde_json::from_str(json)?;
+        if !self.receipt.is_empty() {
+            receipt.extra = pick(&self.receipt, serde_json::from_str(json)?)?;
+        }
+        Ok(receipt)
+    }
+}
+
+fn pick(
+    names: &[&str],
+    fields: HashMap<String, &RawValue>,
+) -> Result<serde_json::Map<String, serde_json::Value>, serde_json::Error> {
+    names
+        .iter()
+        .filter_map(|name| fields.get(*name).map(|v| (name, v)))
+        .map(|(name, v)| Ok((name.to_string(), serde_json::from_str(v.get())?)))
+        .collect()
+}
+
+/// A node in the call tree produced by debug_traceBlockByNumber's callTracer
+#[derive(Deserialize, Debug)]
+pub struct CallFrame {
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub from: Address,
+    pub to: Option<Address>,
+    pub value: Option<U256>,
+    pub gas: Option<U256>,
+    #[serde(rename = "gasUsed")]
+    pub gas_used: Option<U256>,
+    #[serde(default)]
+    pub input: Bytes,
+    pub output: Option<Bytes>,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub calls: Vec<CallFrame>,
+}
+
+impl CallFrame {
+    /// Depth first list of the frame and its sub-calls along with each frame's depth
+    pub fn flatten(&self) -> Vec<(usize, &CallFrame)> {
+        let mut frames = vec![];
+        let mut stack = vec![(0, self)];
+        while let Some((depth, frame)) = stack.pop() {
+            frames.push((depth, frame));
+            stack.extend(frame.calls.iter().rev().map(|c| (depth + 1, c)));
+        }
+        frames
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TxTrace {
+    #[serde(rename = "txHash")]
+    pub tx_hash: Option<BlockHash>,
+    pub result: Option<CallFrame>,
+    pub error: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -97,12 +318,127 @@ pub struct Block {
     #[serde(rename = "extraData")]
     pub extra_data: Bytes,
     pub miner: Address,
+    #[serde(rename = "baseFeePerGas")]
+    pub base_fee_per_gas: Option<U256>,
+    #[serde(rename = "blobGasUsed")]
+    pub blob_gas_used: Option<U64>,
+    #[serde(rename = "excessBlobGas")]
+    pub excess_blob_gas: Option<U64>,
+    #[serde(rename = "withdrawalsRoot")]
+    pub withdrawals_root: Option<FixedBytes<32>>,
+    #[serde(default)]
+    pub withdrawals: Vec<Withdrawal>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Withdrawal {
+    pub index: U64,
+    #[serde(rename = "validatorIndex")]
+    pub validator_index: U64,
+    pub address: Address,
+    // denominated in gwei
+    pub amount: U64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Header {
+    pub hash: BlockHash,
+    #[serde(rename = "parentHash")]
+    pub parent_hash: BlockHash,
+    pub number: U64,
+}
+
+// penalties, in milliseconds, added to an endpoint's latency when scoring
+const ERROR_PENALTY: f64 = 10_000.0;
+const LAG_PENALTY: f64 = 1_000.0;
+// weight of the newest sample in the moving averages
+const ALPHA: f64 = 0.2;
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Health {
+    pub latency_ms: f64,
+    pub error_rate: f64,
+    pub head: u64,
+    pub lag: u64,
+    pub requests: u64,
+    pub errors: u64,
+    // largest eth_getLogs block range the endpoint has accepted
+    pub log_limit: Option<u64>,
+    // set when the endpoint responds with a 429
+    #[serde(skip)]
+    pub limited_until: Option<Instant>,
+}
+
+impl Health {
+    /// Lower is better
+    pub fn score(&self) -> f64 {
+        self.latency_ms + self.error_rate * ERROR_PENALTY + self.lag as f64 * LAG_PENALTY
+    }
+
+    pub fn rate_limited(&self) -> bool {
+        self.limited_until.is_some_and(|t| t > Instant::now())
+    }
+
+    fn lower_log_limit(&mut self, n: u64) {
+        self.log_limit = Some(self.log_limit.map_or(n, |limit| limit.min(n)));
+    }
+
+    // Grows the limit after a successful request at the limit
+    // so that a burst of dense blocks doesn't shrink it forever.
+    fn raise_log_limit(&mut self, n: u64) {
+        if let Some(limit) = self.log_limit.filter(|limit| n >= *limit) {
+            self.log_limit = Some(limit + (limit / 8).max(1));
+        }
+    }
+
+    fn record(&mut self, latency: Duration, ok: bool) {
+        self.requests += 1;
+        if ok {
+            let ms = latency.as_secs_f64() * 1000.0;
+            self.latency_ms = match self.requests {
+                1 => ms,
+                _ => ALPHA * ms + (1.0 - ALPHA) * self.latency_ms,
+            };
+            self.error_rate *= 1.0 - ALPHA;
+        } else {
+            self.errors += 1;
+            self.error_rate = ALPHA + (1.0 - ALPHA) * self.error_rate;
+        }
+    }
+}
+
+struct Endpoint {
+    url: String,
+    health: Mutex<Health>,
+}
+
+impl Endpoint {
+    // The url may contain an api key so only the host is displayed
+    fn host(&self) -> String {
+        url::Url::parse(&self.url)
+            .ok()
+            .and_then(|u| u.host_str().map(str::to_string))
+            .unwrap_or_else(|| String::from("invalid"))
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct EndpointStatus {
+    pub host: String,
+    pub score: f64,
+    pub rate_limited: bool,
+    #[serde(flatten)]
+    pub health: Health,
 }
 
 #[derive(Default)]
 pub struct Client {
-    url: String,
+    endpoints: Vec<Endpoint>,
+    ws_url: Option<String>,
     http_client: reqwest::Client,
+    // set after the provider rejects eth_getBlockReceipts
+    no_block_receipts: AtomicBool,
+    extra: ExtraFields,
 }
 
 #[derive(Deserialize)]
@@ -112,8 +448,46 @@ enum RpcEither<T> {
     Err { error: Error },
 }
 
+#[derive(Deserialize)]
+struct Notification<T> {
+    params: NotificationParams<T>,
+}
+
+#[derive(Deserialize)]
+struct NotificationParams<T> {
+    result: T,
+}
+
+// Some providers respond to a batch with a single error
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BatchEither<T> {
+    Ok(Vec<RpcEither<T>>),
+    Err { error: Error },
+}
+
+#[derive(Deserialize)]
+struct CallResponse {
+    id: usize,
+    result: Option<Bytes>,
+    error: Option<Error>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CallsEither {
+    Ok(Vec<CallResponse>),
+    Err { error: Error },
+}
+
 impl Client {
     pub fn new(url: &str) -> Self {
+        Self::with_urls(&[url])
+    }
+
+    /// Requests are sent to the healthiest endpoint
+    /// and retried on the others when it fails.
+    pub fn with_urls<S: AsRef<str>>(urls: &[S]) -> Self {
         let http_client = reqwest::Client::builder()
             .timeout(Duration::from_secs(30))
             .gzip(true)
@@ -122,37 +496,264 @@ impl Client {
             .unwrap();
         Client {
             http_client,
-            url: url.to_string(),
+            endpoints: urls
+                .iter()
+                .map(|url| Endpoint {
+                    url: url.as_ref().to_string(),
+                    health: Mutex::new(Health::default()),
+                })
+                .collect(),
+            ws_url: None,
+            no_block_receipts: AtomicBool::new(false),
+            extra: ExtraFields::default(),
         }
     }
 
+    pub fn with_extra_fields(mut self, extra: ExtraFields) -> Self {
+        self.extra = extra;
+        self
+    }
+
+    pub fn with_ws_url(mut self, url: Option<&str>) -> Self {
+        self.ws_url = url.map(str::to_string);
+        self
+    }
+
+    pub fn has_ws(&self) -> bool {
+        self.ws_url.is_some()
+    }
+
+    /// Subscribes to newHeads using the websocket url. The receiver
+    /// is updated with the number of each new head and is closed
+    /// when the socket drops.
     #[tracing::instrument(level="info" skip_all)]
-    pub async fn chain_id(&self) -> Result<U64, Error> {
+    pub async fn new_heads(&self) -> Result<watch::Receiver<u64>, Error> {
+        let url = self.ws_url.as_ref().ok_or_else(|| Error {
+            code: -1,
+            message: String::from("missing websocket url"),
+            retry_after: None,
+        })?;
+        let (mut socket, _) = tokio::time::timeout(
+            Duration::from_secs(10),
+            tokio_tungstenite::connect_async(url),
+        )
+        .await
+        .map_err(|_| Error {
+            code: -1,
+            message: String::from("websocket connect timeout"),
+            retry_after: None,
+        })??;
         let request = serde_json::json!({
             "id": 1,
             "jsonrpc": "2.0",
-            "method": "eth_chainId",
+            "method": "eth_subscribe",
+            "params": ["newHeads"],
+        });
+        socket.send(Message::text(request.to_string())).await?;
+        loop {
+            match socket.next().await {
+                Some(Ok(Message::Text(text))) => {
+                    match serde_json::from_str::<RpcEither<String>>(&text) {
+                        Ok(RpcEither::Ok { .. }) => break,
+                        Ok(RpcEither::Err { error }) => return Err(error),
+                        Err(e) => {
+                            return Err(Error {
+                                code: -1,
+                                message: format!("decoding subscription: {e:?}"),
+                                retry_after: None,
+                            })
+                        }
+                    }
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(e.into()),
+                None => {
+                    return Err(Error {
+                        code: -1,
+                        message: String::from("websocket closed"),
+                        retry_after: None,
+                    })
+                }
+            }
+        }
+        let (tx, rx) = watch::channel(0);
+        tokio::spawn(async move {
+            while let Some(Ok(msg)) = socket.next().await {
+                let head = match msg {
+                    Message::Text(text) => serde_json::from_str::<Notification<Header>>(&text),
+                    Message::Close(_) => break,
+                    _ => continue,
+                };
+                match head {
+                    Ok(head) => {
+                        if tx.send(head.params.result.number.to()).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => tracing::warn!("decoding new head: {:?}", e),
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    pub fn status(&self) -> Vec<EndpointStatus> {
+        self.endpoints
+            .iter()
+            .map(|e| {
+                let health = e.health.lock().unwrap().clone();
+                EndpointStatus {
+                    host: e.host(),
+                    score: health.score(),
+                    rate_limited: health.rate_limited(),
+                    health,
+                }
+            })
+            .collect()
+    }
+
+    /// Requests the latest block number from every endpoint
+    /// and updates each endpoint's lag behind the highest head.
+    #[tracing::instrument(level="debug" skip_all)]
+    pub async fn probe(&self) {
+        let request = serde_json::json!({
+            "id": 1,
+            "jsonrpc": "2.0",
+            "method": "eth_blockNumber",
             "params": [],
         });
+        let heads = futures::future::join_all(self.endpoints.iter().map(|endpoint| async {
+            match self.post::<RpcEither<U64>>(endpoint, &request).await {
+                Ok(RpcEither::Ok { result }) => Some(result.to::<u64>()),
+                _ => None,
+            }
+        }))
+        .await;
+        let max = heads.iter().flatten().max().copied().unwrap_or_default();
+        for (endpoint, head) in self.endpoints.iter().zip(heads) {
+            let mut health = endpoint.health.lock().unwrap();
+            if let Some(head) = head {
+                health.head = head;
+            }
+            health.lag = max.saturating_sub(health.head);
+        }
+    }
 
-        let response: RpcEither<U64> = self
-            .http_client
-            .post(&self.url)
-            .json(&request)
-            .send()
-            .await
-            .map_err(|e| Error {
-                code: -1,
-                message: e.to_string(),
-            })?
-            .json()
-            .await
-            .map_err(|e| Error {
+    // Rate limited endpoints are only used after all the others
+    fn ranked(&self) -> Vec<&Endpoint> {
+        self.endpoints
+            .iter()
+            .sorted_by(|a, b| {
+                let (a, b) = (a.health.lock().unwrap(), b.health.lock().unwrap());
+                (a.rate_limited().cmp(&b.rate_limited())).then(a.score().total_cmp(&b.score()))
+            })
+            .collect_vec()
+    }
+
+    /// Sends the request to endpoints in order of their score
+    /// until one of them responds with a decodable response.
+    async fn send<T: DeserializeOwned>(&self, request: &impl Serialize) -> Result<T, Error> {
+        let mut err = Error {
+            code: -1,
+            message: String::from("no endpoints"),
+            retry_after: None,
+        };
+        for endpoint in self.ranked() {
+            match self.post(endpoint, request).await {
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    tracing::warn!("endpoint {} failed: {}", endpoint.host(), e);
+                    err = e;
+                }
+            }
+        }
+        Err(err)
+    }
+
+    async fn post<T: DeserializeOwned>(
+        &self,
+        endpoint: &Endpoint,
+        request: &impl Serialize,
+    ) -> Result<T, Error> {
+        let start = Instant::now();
+        let result = async {
+            let response = self
+                .http_client
+                .post(&endpoint.url)
+                .json(request)
+                .send()
+                .await
+                .map_err(|e| Error {
+                    code: -1,
+                    message: format!("sending request: {e:?}"),
+                    retry_after: None,
+                })?;
+            let status = response.status();
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .map(Duration::from_secs);
+            let body = response.text().await.map_err(|e| Error {
                 code: -1,
-                message: e.to_string(),
+                message: format!("reading response: {e:?}"),
+                retry_after: None,
             })?;
+            if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                let backoff = retry_after.unwrap_or(Duration::from_secs(1));
+                endpoint.health.lock().unwrap().limited_until = Some(Instant::now() + backoff);
+                return Err(Error {
+                    code: status.as_u16() as i64,
+                    message: format!("rate limited: {body}"),
+                    retry_after,
+                });
+            }
+            if !status.is_success() {
+                // some providers send the json-rpc error with a 4xx or 5xx
+                return Err(match serde_json::from_str::<RpcEither<()>>(&body) {
+                    Ok(RpcEither::Err { error }) => Error {
+                        message: format!("http status {status}: {}", error.message),
+                        ..error
+                    },
+                    _ => Error {
+                        code: status.as_u16() as i64,
+                        message: format!("http status {status}: {body}"),
+                        retry_after: None,
+                    },
+                });
+            }
+            serde_json::from_str::<T>(&body).map_err(|e| Error {
+                code: -1,
+                message: format!("decode error: {e:?}\n{body}\n"),
+                retry_after: None,
+            })
+        }
+        .await;
+        let host = endpoint.host();
+        metrics::histogram!("jrpc_request_duration_seconds", "host" => host.clone())
+            .record(start.elapsed().as_secs_f64());
+        if result.is_err() {
+            metrics::counter!("jrpc_errors_total", "host" => host).increment(1);
+        }
+        endpoint
+            .health
+            .lock()
+            .unwrap()
+            .record(start.elapsed(), result.is_ok());
+        result
+    }
 
-        match response {
+    #[tracing::instrument(level="info" skip_all)]
+    pub async fn chain_id(&self) -> Result<U64, Error> {
+        let request = serde_json::json!({
+            "id": 1,
+            "jsonrpc": "2.0",
+            "method": "eth_chainId",
+            "params": [],
+        });
+
+        match self.send::<RpcEither<U64>>(&request).await? {
             RpcEither::Ok { result, .. } => Ok(result),
             RpcEither::Err { error, .. } => Err(error),
         }
@@ -170,29 +771,16 @@ impl Client {
                 })
             })
             .collect();
-        let response_body = self
-            .http_client
-            .post(&self.url)
-            .json(&request)
-            .send()
-            .await
-            .map_err(|e| Error {
-                code: -1,
-                message: format!("decoding blocks: {e:?}"),
-            })?
-            .text()
-            .await
-            .map_err(|e| Error {
-                code: -1,
-                message: format!("decoding blocks json: {e:?}"),
-            })?;
-
-        let response: Vec<RpcEither<Block>> =
-            serde_json::from_str(&response_body).map_err(|e| Error {
-                code: -1,
-                message: format!("decode error: {e:?}\n{}\n", response_body),
-            })?;
-
+        if !self.extra.tx.is_empty() {
+            let blocks = self
+                .batch_raw(&request, |json| self.extra.block(json))
+                .await?;
+            return Ok(blocks
+                .into_iter()
+                .sorted_by(|a, b| a.number.cmp(&b.number))
+                .collect());
+        }
+        let response: Vec<RpcEither<Block>> = self.send(&request).await?;
         Ok(response
             .into_iter()
             .map(|r| match r {
@@ -208,72 +796,423 @@ impl Client {
             .collect())
     }
 
-    #[tracing::instrument(level="info" skip_all, fields(number = %number))]
-    pub async fn block(&self, number: String) -> Result<Block, Error> {
+    #[tracing::instrument(level="info" skip_all, fields(from, to))]
+    pub async fn headers(&self, from: u64, to: u64) -> Result<Vec<Header>, Error> {
+        let request: Vec<_> = (from..=to)
+            .map(|n| {
+                serde_json::json!({
+                    "id": n,
+                    "jsonrpc": "2.0",
+                    "method": "eth_getBlockByNumber",
+                    "params": [format!("0x{n:x}"), false],
+                })
+            })
+            .collect();
+        let response: Vec<RpcEither<Header>> = self.send(&request).await?;
+        Ok(response
+            .into_iter()
+            .map(|r| match r {
+                RpcEither::Ok { result, .. } => Ok(result),
+                RpcEither::Err { error, .. } => Err(error),
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .sorted_by(|a, b| a.number.cmp(&b.number))
+            .collect())
+    }
+
+    #[tracing::instrument(level="info" skip_all, fields(tag = %tag))]
+    pub async fn header(&self, tag: &str) -> Result<Header, Error> {
         let request = serde_json::json!({
             "id": "1",
             "jsonrpc": "2.0",
             "method": "eth_getBlockByNumber",
-            "params": [number, true],
+            "params": [tag, false],
         });
-        let response: RpcEither<Block> = self
-            .http_client
-            .post(&self.url)
-            .json(&request)
-            .send()
-            .await
-            .map_err(|e| Error {
-                code: -1,
-                message: format!("decoding block: {e:?}"),
-            })?
-            .json()
-            .await
-            .map_err(|e| Error {
-                code: -1,
-                message: format!("decoding block json: {e:?}"),
-            })?;
-        match response {
+        match self.send::<RpcEither<Header>>(&request).await? {
             RpcEither::Ok { result, .. } => Ok(result),
             RpcEither::Err { error, .. } => Err(error),
         }
     }
 
-    #[tracing::instrument(level="info" skip_all, fields(from, to))]
-    pub async fn logs(&self, from: u64, to: u64) -> Result<Vec<Log>, Error> {
+    #[tracing::instrument(level="info" skip_all, fields(number = %number))]
+    pub async fn block(&self, number: String) -> Result<Block, Error> {
         let request = serde_json::json!({
             "id": "1",
             "jsonrpc": "2.0",
-            "method": "eth_getLogs",
-            "params": [{
-                "fromBlock": format!("0x{:x}", from),
-                "toBlock":   format!("0x{:x}", to),
-            }],
+            "method": "eth_getBlockByNumber",
+            "params": [number, true],
         });
-        match self
-            .http_client
-            .post(&self.url)
-            .json(&request)
-            .send()
-            .await
-            .map_err(|e| Error {
-                code: -1,
-                message: e.to_string(),
-            })?
-            .json::<RpcEither<Vec<Log>>>()
-            .await
-            .map_err(|e| Error {
-                code: -1,
-                message: format!("decoding logs: {e:?}"),
-            })? {
+        match self.send::<RpcEither<Block>>(&request).await? {
             RpcEither::Ok { result, .. } => Ok(result),
             RpcEither::Err { error, .. } => Err(error),
         }
     }
+
+    /// Downloads logs from the healthiest endpoint
+    /// and tries the others when it fails.
+    #[tracing::instrument(level="info" skip_all, fields(from, to))]
+    pub async fn logs(&self, from: u64, to: u64, filter: &LogFilter) -> Result<Vec<Log>, Error> {
+        let mut err = Error {
+            code: -1,
+            message: String::from("no endpoints"),
+            retry_after: None,
+        };
+        for endpoint in self.ranked() {
+            match self.endpoint_logs(endpoint, from, to, filter).await {
+                Ok(logs) => return Ok(logs),
+                Err(e) => {
+                    tracing::warn!("endpoint {} logs failed: {}", endpoint.host(), e);
+                    err = e;
+                }
+            }
+        }
+        Err(err)
+    }
+
+    /// Ranges larger than the endpoint's learned limit are split up front.
+    /// Ranges that the endpoint rejects as too large are bisected,
+    /// the limit is lowered, and the halves are downloaded in order.
+    async fn endpoint_logs(
+        &self,
+        endpoint: &Endpoint,
+        from: u64,
+        to: u64,
+        filter: &LogFilter,
+    ) -> Result<Vec<Log>, Error> {
+        let limit = endpoint.health.lock().unwrap().log_limit;
+        let mut ranges = split_range(from, to, limit);
+        ranges.reverse();
+        let mut logs = vec![];
+        while let Some((from, to)) = ranges.pop() {
+            let mut params = serde_json::json!({
+                "fromBlock": format!("0x{:x}", from),
+                "toBlock":   format!("0x{:x}", to),
+            });
+            if !filter.addresses.is_empty() {
+                params["address"] = serde_json::json!(filter.addresses);
+            }
+            if !filter.topics.is_empty() {
+                params["topics"] = serde_json::json!([filter.topics]);
+            }
+            let request = serde_json::json!({
+                "id": "1",
+                "jsonrpc": "2.0",
+                "method": "eth_getLogs",
+                "params": [params],
+            });
+            let response = match self.post::<RpcEither<Vec<Log>>>(endpoint, &request).await {
+                Ok(RpcEither::Ok { result }) => Ok(result),
+                Ok(RpcEither::Err { error }) | Err(error) => Err(error),
+            };
+            match response {
+                Ok(result) => {
+                    endpoint
+                        .health
+                        .lock()
+                        .unwrap()
+                        .raise_log_limit(to - from + 1);
+                    logs.extend(result);
+                }
+                Err(error) if error.too_many_logs() && from < to => {
+                    let mid = from + (to - from) / 2;
+                    tracing::debug!("bisecting logs {}-{}: {}", from, to, error);
+                    endpoint
+                        .health
+                        .lock()
+                        .unwrap()
+                        .lower_log_limit(mid - from + 1);
+                    ranges.push((mid + 1, to));
+                    ranges.push((from, mid));
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(logs)
+    }
+
+    #[tracing::instrument(level="info" skip_all, fields(from, to))]
+    pub async fn traces(&self, from: u64, to: u64) -> Result<Vec<TxTrace>, Error> {
+        let request: Vec<_> = (from..=to)
+            .map(|n| {
+                serde_json::json!({
+                    "id": n,
+                    "jsonrpc": "2.0",
+                    "method": "debug_traceBlockByNumber",
+                    "params": [format!("0x{n:x}"), {"tracer": "callTracer"}],
+                })
+            })
+            .collect();
+        Ok(self
+            .batch::<Vec<TxTrace>>(&request)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+
+    /// Downloads receipts for every transaction in blocks using
+    /// eth_getBlockReceipts. Falls back to eth_getTransactionReceipt
+    /// when the provider doesn't support block receipts.
+    #[tracing::instrument(level="info" skip_all)]
+    pub async fn receipts(&self, blocks: &[Block]) -> Result<Vec<Receipt>, Error> {
+        if !self.no_block_receipts.load(Ordering::Relaxed) {
+            match self.block_receipts(blocks).await {
+                Err(e) if e.unsupported() => {
+                    tracing::warn!("falling back to tx receipts: {}", e);
+                    self.no_block_receipts.store(true, Ordering::Relaxed);
+                }
+                res => return res,
+            }
+        }
+        self.tx_receipts(blocks).await
+    }
+
+    async fn block_receipts(&self, blocks: &[Block]) -> Result<Vec<Receipt>, Error> {
+        let request: Vec<_> = blocks
+            .iter()
+            .map(|b| {
+                serde_json::json!({
+                    "id": b.number,
+                    "jsonrpc": "2.0",
+                    "method": "eth_getBlockReceipts",
+                    "params": [b.number],
+                })
+            })
+            .collect();
+        if !self.extra.receipt.is_empty() {
+            let receipts = self
+                .batch_raw(&request, |json| {
+                    serde_json::from_str::<Vec<&RawValue>>(json)?
+                        .into_iter()
+                        .map(|r| self.extra.receipt(r.get()))
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .await?;
+            return Ok(receipts.into_iter().flatten().collect());
+        }
+        Ok(self
+            .batch::<Vec<Receipt>>(&request)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect())
+    }
+
+    async fn tx_receipts(&self, blocks: &[Block]) -> Result<Vec<Receipt>, Error> {
+        const MAX_BATCH: usize = 500;
+        let request: Vec<_> = blocks
+            .iter()
+            .flat_map(|b| b.transactions.iter())
+            .map(|tx| {
+                serde_json::json!({
+                    "id": tx.hash,
+                    "jsonrpc": "2.0",
+                    "method": "eth_getTransactionReceipt",
+                    "params": [tx.hash],
+                })
+            })
+            .collect();
+        let mut receipts = Vec::with_capacity(request.len());
+        for chunk in request.chunks(MAX_BATCH) {
+            if self.extra.receipt.is_empty() {
+                receipts.extend(self.batch::<Receipt>(chunk).await?);
+            } else {
+                receipts.extend(
+                    self.batch_raw(chunk, |json| self.extra.receipt(json))
+                        .await?,
+                );
+            }
+        }
+        Ok(receipts)
+    }
+
+    /// eth_call at the latest block for each (to, data) pair.
+    /// Calls that revert (eg the contract doesn't implement the function)
+    /// are None. Rate limits fail the whole batch so they can be retried.
+    #[tracing::instrument(level="info" skip_all, fields(n = calls.len()))]
+    pub async fn calls(&self, calls: &[(Address, Bytes)]) -> Result<Vec<Option<Bytes>>, Error> {
+        if calls.is_empty() {
+            return Ok(vec![]);
+        }
+        let request: Vec<_> = calls
+            .iter()
+            .enumerate()
+            .map(|(i, (to, data))| {
+                serde_json::json!({
+                    "id": i,
+                    "jsonrpc": "2.0",
+                    "method": "eth_call",
+                    "params": [{"to": to, "data": data}, "latest"],
+                })
+            })
+            .collect();
+        let response = match self.send::<CallsEither>(&request).await? {
+            CallsEither::Ok(response) => response,
+            CallsEither::Err { error } => return Err(error),
+        };
+        let mut results = vec![None; calls.len()];
+        for r in response {
+            match r.error {
+                Some(error) if error.rate_limited() => return Err(error),
+                Some(_) => {}
+                None => {
+                    if let Some(result) = results.get_mut(r.id) {
+                        *result = r.result;
+                    }
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    async fn batch<T: DeserializeOwned>(
+        &self,
+        request: &[serde_json::Value],
+    ) -> Result<Vec<T>, Error> {
+        if request.is_empty() {
+            return Ok(vec![]);
+        }
+        let response = match self.send::<BatchEither<T>>(&request).await? {
+            BatchEither::Ok(response) => response,
+            BatchEither::Err { error } => return Err(error),
+        };
+        response
+            .into_iter()
+            .map(|r| match r {
+                RpcEither::Ok { result, .. } => Ok(result),
+                RpcEither::Err { error, .. } => Err(error),
+            })
+            .collect()
+    }
+
+    /// Like batch but each result is decoded by decode
+    async fn batch_raw<T>(
+        &self,
+        request: &[serde_json::Value],
+        decode: impl Fn(&str) -> Result<T, serde_json::Error>,
+    ) -> Result<Vec<T>, Error> {
+        self.batch::<Box<RawValue>>(request)
+            .await?
+            .iter()
+            .map(|raw| {
+                decode(raw.get()).map_err(|e| Error {
+                    code: -1,
+                    message: format!("decode error: {e:?}\n{}\n", raw.get()),
+                    retry_after: None,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Splits from..=to into ranges of at most limit blocks
+fn split_range(from: u64, to: u64, limit: Option<u64>) -> Vec<(u64, u64)> {
+    let limit = limit.unwrap_or(u64::MAX).max(1);
+    let mut ranges = vec![];
+    let mut n = from;
+    while n <= to {
+        let end = to.min(n.saturating_add(limit - 1));
+        ranges.push((n, end));
+        if end == u64::MAX {
+            break;
+        }
+        n = end + 1;
+    }
+    ranges
 }
 
 #[cfg(test)]
 mod tests {
     use alloy::primitives::{b256, fixed_bytes, U64};
+    use std::time::Duration;
+
+    #[test]
+    fn test_split_range() {
+        assert_eq!(super::split_range(1, 10, None), vec![(1, 10)]);
+        assert_eq!(
+            super::split_range(1, 10, Some(4)),
+            vec![(1, 4), (5, 8), (9, 10)]
+        );
+        assert_eq!(super::split_range(5, 5, Some(1)), vec![(5, 5)]);
+
+        let mut health = super::Health::default();
+        health.lower_log_limit(500);
+        health.lower_log_limit(1000);
+        assert_eq!(health.log_limit, Some(500));
+        health.raise_log_limit(100);
+        assert_eq!(health.log_limit, Some(500));
+        health.raise_log_limit(500);
+        assert_eq!(health.log_limit, Some(562));
+    }
+
+    #[test]
+    fn test_too_many_logs() {
+        let err = |code: i64, message: &str| super::Error {
+            code,
+            message: message.to_string(),
+            retry_after: None,
+        };
+        assert!(err(-32005, "query returned more than 10000 results").too_many_logs());
+        assert!(err(-32000, "block range too large").too_many_logs());
+        assert!(err(-32602, "Log response size exceeded.").too_many_logs());
+        assert!(err(-32000, "exceed maximum block range: 5000").too_many_logs());
+        assert!(err(
+            400,
+            "http status 400: query returned more than 10000 results"
+        )
+        .too_many_logs());
+        assert!(!err(-32005, "daily request count exceeded, request rate limited").too_many_logs());
+        assert!(!err(-32000, "header not found").too_many_logs());
+        assert!(!err(-32000, "gas required exceeds allowance: more than 1000").too_many_logs());
+        assert!(!err(-32602, "invalid block range params").too_many_logs());
+    }
+
+    #[test]
+    fn test_health_score() {
+        let (mut fast, mut slow) = (super::Health::default(), super::Health::default());
+        fast.record(Duration::from_millis(10), true);
+        slow.record(Duration::from_millis(500), true);
+        assert!(fast.score() < slow.score());
+
+        fast.record(Duration::from_millis(10), false);
+        assert_eq!(fast.errors, 1);
+        assert!(fast.score() > slow.score());
+
+        for _ in 0..20 {
+            fast.record(Duration::from_millis(10), true);
+        }
+        assert!(fast.score() < slow.score());
+
+        fast.lag = 2;
+        assert!(fast.score() > slow.score());
+    }
+
+    #[test]
+    fn test_receipt_extra() {
+        let json = serde_json::json!({
+            "transactionHash": "0x23e3362a76c8b9370dc65bac8eb1cda1d408ac238a466cfe690248025254bf52",
+            "blockNumber": "0x1",
+            "status": "0x1",
+            "gasUsed": "0x5208",
+            "cumulativeGasUsed": "0x5208",
+            "logsBloom": format!("0x{}", "00".repeat(256)),
+            "logs": [{"address": "0x1f573d6fb3f13d689ff844b4ce37794d79a7ff1c"}],
+            "l1Fee": "0x2a",
+        })
+        .to_string();
+        let receipt = super::ExtraFields::default().receipt(&json).unwrap();
+        assert_eq!(receipt.gas_used, alloy::primitives::U256::from(21000));
+        assert!(receipt.extra.is_empty());
+
+        let extra = super::ExtraFields {
+            tx: vec![],
+            receipt: vec!["l1Fee", "l1GasUsed"],
+        };
+        let receipt = extra.receipt(&json).unwrap();
+        assert_eq!(receipt.extra.len(), 1);
+        assert_eq!(receipt.extra["l1Fee"], "0x2a");
+    }
 
     #[test_log::test(tokio::test)]
     async fn test_block_and_logs() {
@@ -282,7 +1221,7 @@ mod tests {
         let client = super::Client::new(&url);
         let n: u64 = 12_911_679;
 
-        let b = client.block(format!("0x{:x}", n)).await.unwrap();
+        let b = client.block(format!("0x{n:x}")).await.unwrap();
         assert_eq!(
             b.hash,
             b256!("a917fcc721a5465a484e9be17cda0cc5493933dd3bc70c9adbee192cb419c9d7")
@@ -292,7 +1231,7 @@ mod tests {
             b256!("23e3362a76c8b9370dc65bac8eb1cda1d408ac238a466cfe690248025254bf52")
         );
 
-        let logs = client.logs(n, n).await.unwrap();
+        let logs = client.logs(n, n, &Default::default()).await.unwrap();
         let l = logs.first().expect("missing logs");
         assert_eq!(l.block_number, U64::from(n));
         assert_eq!(
@@ -2,18 +2,24 @@ use crate::Error;
 
 use deadpool_postgres::{Manager, ManagerConfig, Pool};
 use eyre::{Context, Result};
+use futures::{stream, StreamExt};
 use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
 use postgres_openssl::MakeTlsConnector;
-use std::str::FromStr;
+use std::{str::FromStr, time::Duration};
+use tokio::sync::mpsc;
+use tokio_postgres::AsyncMessage;
 
-pub fn new_pool(url: &str, size: usize) -> Result<Pool> {
-    let pg_config = tokio_postgres::Config::from_str(url)?;
+fn tls() -> Result<MakeTlsConnector> {
     let mut builder = SslConnector::builder(SslMethod::tls())?;
     builder.set_verify(SslVerifyMode::NONE);
-    let connector = MakeTlsConnector::new(builder.build());
+    Ok(MakeTlsConnector::new(builder.build()))
+}
+
+pub fn new_pool(url: &str, size: usize) -> Result<Pool> {
+    let pg_config = tokio_postgres::Config::from_str(url)?;
     let pg_mgr = Manager::from_config(
         pg_config,
-        connector,
+        tls()?,
         ManagerConfig {
             recycling_method: deadpool_postgres::RecyclingMethod::Fast,
         },
@@ -36,6 +42,87 @@ pub fn unique_violations(err: tokio_postgres::Error, map: &[(&str, &str)]) -> Er
         .unwrap_or_else(|| err.into())
 }
 
+/// Waits for NOTIFY on a set of channels. Pooled connections drop
+/// notifications so the Listener keeps its own connection and
+/// reconnects on the next wait after it's lost.
+pub struct Listener {
+    url: String,
+    channels: Vec<String>,
+    conn: Option<(tokio_postgres::Client, mpsc::UnboundedReceiver<String>)>,
+}
+
+impl Listener {
+    pub fn new(url: &str, channels: &[&str]) -> Listener {
+        Listener {
+            url: url.to_string(),
+            channels: channels.iter().map(|c| c.to_string()).collect(),
+            conn: None,
+        }
+    }
+
+    async fn connect(&self) -> Result<(tokio_postgres::Client, mpsc::UnboundedReceiver<String>)> {
+        let pg_config = tokio_postgres::Config::from_str(&self.url)?;
+        let (client, mut connection) = pg_config.connect(tls()?).await?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+            while let Some(msg) = messages.next().await {
+                match msg {
+                    Ok(AsyncMessage::Notification(n)) => {
+                        if tx.send(n.payload().to_string()).is_err() {
+                            return;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        tracing::error!("listener connection: {}", e);
+                        return;
+                    }
+                }
+            }
+        });
+        let listen = self
+            .channels
+            .iter()
+            .map(|c| format!("listen {c};"))
+            .collect::<String>();
+        client.batch_execute(&listen).await.wrap_err("listen")?;
+        Ok((client, rx))
+    }
+
+    /// Returns after a notification or after timeout, whichever is first,
+    /// with the payloads of the notifications that arrived since the last wait.
+    /// Also returns right after (re)connecting and when the connection is
+    /// lost since notifications may have been missed while not listening.
+    /// Callers should reload either way.
+    pub async fn wait(&mut self, timeout: Duration) -> Vec<String> {
+        let Some((_, rx)) = self.conn.as_mut() else {
+            match self.connect().await {
+                Ok(conn) => self.conn = Some(conn),
+                Err(e) => {
+                    tracing::error!("listening on {:?}: {:?}", self.channels, e);
+                    tokio::time::sleep(timeout).await;
+                }
+            }
+            return vec![];
+        };
+        match tokio::time::timeout(timeout, rx.recv()).await {
+            Ok(Some(payload)) => {
+                let mut payloads = vec![payload];
+                while let Ok(payload) = rx.try_recv() {
+                    payloads.push(payload);
+                }
+                payloads
+            }
+            Ok(None) => {
+                self.conn = None;
+                vec![]
+            }
+            Err(_) => vec![],
+        }
+    }
+}
+
 #[cfg(feature = "test")]
 pub mod test {
     use deadpool_postgres::Pool;
@@ -71,7 +158,7 @@ pub mod test {
             .expect("create db");
 
         let mut u = Url::parse(admin_db_url).unwrap();
-        u.set_path(&format!("/{}", name));
+        u.set_path(&format!("/{name}"));
         let db_url = u.to_string();
 
         drop(admin);
@@ -118,3 +205,34 @@ pub mod test {
         ts.parse().ok()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{new_pool, Listener};
+
+    #[tokio::test]
+    async fn test_listener() {
+        let url = "postgres://golden_axe@localhost:5432/golden_axe_test";
+        let mut listener = Listener::new(url, &["test_listener"]);
+        let start = Instant::now();
+        listener.wait(Duration::from_secs(30)).await;
+        assert!(start.elapsed() < Duration::from_secs(10), "connecting");
+
+        let pool = new_pool(url, 1).unwrap();
+        let pg = pool.get().await.unwrap();
+        pg.batch_execute("notify test_listener, 'a'; notify test_listener, 'b'")
+            .await
+            .unwrap();
+        let mut payloads = listener.wait(Duration::from_secs(30)).await;
+        if payloads.len() == 1 {
+            payloads.extend(listener.wait(Duration::from_secs(30)).await);
+        }
+        assert_eq!(payloads, vec!["a", "b"]);
+
+        let start = Instant::now();
+        assert!(listener.wait(Duration::from_millis(100)).await.is_empty());
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}